@@ -0,0 +1,62 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+// Test builds route every allocation through this so tests can check how
+// much a piece of code allocates. Counts are per thread because the test
+// harness runs tests in parallel.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOC_COUNT: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOC_COUNT.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOC_COUNT.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Number of allocations (including reallocations) on this thread since the
+// last reset
+pub fn alloc_count() -> usize {
+    ALLOC_COUNT.with(|count| count.get())
+}
+
+pub fn reset_alloc_count() {
+    ALLOC_COUNT.with(|count| count.set(0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_allocations() {
+        reset_alloc_count();
+        assert_eq!(alloc_count(), 0);
+
+        let boxed = Box::new(1);
+        assert_eq!(alloc_count(), 1);
+
+        drop(boxed);
+        assert_eq!(alloc_count(), 1);
+
+        reset_alloc_count();
+        assert_eq!(alloc_count(), 0);
+    }
+}
@@ -0,0 +1,463 @@
+use std::rc::Rc;
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    peephole::{instruction_length, read_short},
+    value::{Class, Function, Value},
+};
+
+// Compiled programs (`.loxc` files) start with this so we can tell them
+// apart from source files and from bytecode written by another version
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u8 = 3;
+
+// One tag byte per kind of value that can end up in a constant pool
+const TAG_NIL: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_INT: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+const TAG_CLASS: u8 = 6;
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    current: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, current: 0 }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.current)
+    }
+
+    // Anything left over means the bytes weren't what we thought they were
+    pub fn finish(&self) -> Result<(), String> {
+        if self.current < self.bytes.len() {
+            return Err(self.error("Unexpected trailing bytes"));
+        }
+        return Ok(());
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.current < length {
+            return Err(self.error("Unexpected end of bytecode"));
+        }
+
+        let bytes = &self.bytes[self.current..(self.current + length)];
+        self.current += length;
+        return Ok(bytes);
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        return Ok(self.read_bytes(1)?[0]);
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.read_bytes(4)?;
+        return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn read_u64(&mut self) -> Result<u64, String> {
+        let bytes = self.read_bytes(8)?;
+        return Ok(u64::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        let length = self.read_u32()? as usize;
+        let bytes = self.read_bytes(length)?;
+
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => return Ok(s),
+            Err(_) => return Err(self.error("Invalid UTF-8 in string")),
+        }
+    }
+
+    pub fn read_chunk(&mut self) -> Result<Chunk, String> {
+        let mut chunk = Chunk::new();
+
+        let code_length = self.read_u32()? as usize;
+        chunk.code = self.read_bytes(code_length)?.to_vec();
+
+        // Lines are stored as runs, which between them have to cover the code
+        let run_count = self.read_u32()? as usize;
+        let mut covered = 0;
+        for _ in 0..run_count {
+            let line = self.read_u32()? as usize;
+            let run_length = self.read_u32()? as usize;
+            covered += run_length;
+            chunk.lines.push((line, run_length));
+        }
+        if covered != code_length {
+            return Err(self.error("Line runs don't match the code length"));
+        }
+
+        let constant_count = self.read_u32()? as usize;
+        for _ in 0..constant_count {
+            let constant = self.read_value()?;
+            chunk.constants.push(constant);
+        }
+
+        return Ok(chunk);
+    }
+
+    pub fn read_function(&mut self) -> Result<Function, String> {
+        let mut function = Function::new();
+
+        function.arity = self.read_u8()?;
+        function.upvalue_count = self.read_u8()?;
+        function.is_getter = self.read_u8()? != 0;
+        function.name = match self.read_u8()? {
+            0 => None,
+            _ => Some(self.read_string()?),
+        };
+        function.chunk = self.read_chunk()?;
+        check_code(&function)?;
+
+        return Ok(function);
+    }
+
+    fn read_value(&mut self) -> Result<Value, String> {
+        match self.read_u8()? {
+            TAG_NIL => return Ok(Value::Nil),
+            TAG_BOOLEAN => return Ok(Value::Boolean(self.read_u8()? != 0)),
+            TAG_INT => return Ok(Value::Int(self.read_u64()? as i64)),
+            TAG_NUMBER => return Ok(Value::Number(f64::from_bits(self.read_u64()?))),
+            TAG_STRING => return Ok(Value::String(self.read_string()?.into())),
+            TAG_FUNCTION => return Ok(Value::Function(Rc::new(self.read_function()?))),
+            TAG_CLASS => {
+                return Ok(Value::Class(Rc::new(Class::new(self.read_string()?))));
+            }
+            tag => {
+                self.current -= 1;
+                return Err(self.error(format!("Unknown value tag {}", tag).as_str()));
+            }
+        }
+    }
+}
+
+// The VM trusts the compiler to only emit instructions it can run, but a
+// `.loxc` file could hold anything. Every instruction is checked once here
+// so its operands stay inside the code, the constant pool and the upvalues.
+fn check_code(function: &Function) -> Result<(), String> {
+    let chunk = &function.chunk;
+    let mut starts = vec![false; chunk.code.len()];
+    let mut last = None;
+    let mut jumps = Vec::new();
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        starts[offset] = true;
+        last = Some(offset);
+
+        let Some(op) = OpCode::from_u8(chunk.code[offset]) else {
+            return Err(format!(
+                "Unknown opcode {} at offset {}",
+                chunk.code[offset], offset
+            ));
+        };
+        let operand = chunk.code.get(offset + 1).copied();
+
+        // A closure's length depends on the function it closes over, so the
+        // constant has to be checked before anything else
+        match op {
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::Closure
+            | OpCode::Class
+            | OpCode::GetProperty
+            | OpCode::GetSuper
+            | OpCode::SetProperty
+            | OpCode::Method => match operand {
+                Some(index) if (index as usize) < chunk.constants.len() => {}
+                _ => return Err(format!("Constant index out of range at offset {}", offset)),
+            },
+            OpCode::GetUpvalue | OpCode::SetUpvalue => match operand {
+                Some(index) if index < function.upvalue_count => {}
+                _ => return Err(format!("Upvalue index out of range at offset {}", offset)),
+            },
+            _ => {}
+        }
+        if let OpCode::Closure = op {
+            if !matches!(
+                chunk.constants[operand.unwrap() as usize],
+                Value::Function(_)
+            ) {
+                return Err(format!("Closure of a non-function at offset {}", offset));
+            }
+        }
+
+        let length = instruction_length(chunk, offset);
+        if offset + length > chunk.code.len() {
+            return Err(format!(
+                "Instruction at offset {} runs past the end of the code",
+                offset
+            ));
+        }
+
+        match op {
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                jumps.push((
+                    offset,
+                    Some(offset + 3 + read_short(&chunk.code, offset + 1)),
+                ));
+            }
+            OpCode::Loop => {
+                jumps.push((
+                    offset,
+                    (offset + 3).checked_sub(read_short(&chunk.code, offset + 1)),
+                ));
+            }
+            // Upvalues that aren't locals come from the enclosing function
+            OpCode::Closure => {
+                for pair in chunk.code[(offset + 2)..(offset + length)].chunks(2) {
+                    if pair[0] != 1 && pair[1] >= function.upvalue_count {
+                        return Err(format!("Upvalue index out of range at offset {}", offset));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        offset += length;
+    }
+
+    // Falling off the end would read past the code
+    match last {
+        Some(last) if chunk.code[last] == OpCode::Return as u8 => {}
+        _ => return Err(String::from("Code doesn't end with a return")),
+    }
+
+    for (offset, target) in jumps {
+        match target {
+            Some(target) if starts.get(target) == Some(&true) => {}
+            _ => return Err(format!("Jump at offset {} lands outside the code", offset)),
+        }
+    }
+
+    return Ok(());
+}
+
+fn write_u32(output: &mut Vec<u8>, n: usize) {
+    output.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn write_string(output: &mut Vec<u8>, s: &str) {
+    write_u32(output, s.len());
+    output.extend_from_slice(s.as_bytes());
+}
+
+pub fn write_chunk(output: &mut Vec<u8>, chunk: &Chunk) {
+    write_u32(output, chunk.code.len());
+    output.extend_from_slice(&chunk.code);
+
+    write_u32(output, chunk.lines.len());
+    for (line, run_length) in &chunk.lines {
+        write_u32(output, *line);
+        write_u32(output, *run_length);
+    }
+
+    write_u32(output, chunk.constants.len());
+    for constant in &chunk.constants {
+        write_value(output, constant);
+    }
+}
+
+pub fn write_function(output: &mut Vec<u8>, function: &Function) {
+    output.push(function.arity);
+    output.push(function.upvalue_count);
+    output.push(function.is_getter as u8);
+    match &function.name {
+        Some(name) => {
+            output.push(1);
+            write_string(output, name);
+        }
+        None => output.push(0),
+    }
+    write_chunk(output, &function.chunk);
+}
+
+fn write_value(output: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => output.push(TAG_NIL),
+        Value::Boolean(b) => {
+            output.push(TAG_BOOLEAN);
+            output.push(*b as u8);
+        }
+        Value::Int(n) => {
+            output.push(TAG_INT);
+            output.extend_from_slice(&n.to_le_bytes());
+        }
+        Value::Number(n) => {
+            output.push(TAG_NUMBER);
+            output.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        Value::String(s) => {
+            output.push(TAG_STRING);
+            write_string(output, s);
+        }
+        Value::Function(function) => {
+            output.push(TAG_FUNCTION);
+            write_function(output, function);
+        }
+        // Methods are attached by OP_METHOD at runtime, so a class constant
+        // is nothing more than its name
+        Value::Class(class) => {
+            output.push(TAG_CLASS);
+            write_string(output, &class.name);
+        }
+        value => unreachable!("{} can't be stored in a constant pool", value),
+    }
+}
+
+// A whole compiled program, i.e. the top level script function
+pub fn serialize_program(function: &Function) -> Vec<u8> {
+    let mut output = Vec::new();
+    output.extend_from_slice(MAGIC);
+    output.push(VERSION);
+    write_function(&mut output, function);
+    return output;
+}
+
+pub fn deserialize_program(bytes: &[u8]) -> Result<Function, String> {
+    let mut reader = Reader::new(bytes);
+
+    if reader.read_bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(String::from("Not a compiled rlox program"));
+    }
+
+    let version = reader.read_u8()?;
+    if version != VERSION {
+        return Err(format!(
+            "Compiled with bytecode version {} but this is version {}",
+            version, VERSION
+        ));
+    }
+
+    let function = reader.read_function()?;
+    reader.finish()?;
+
+    return Ok(function);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compiler::{Compiler, FunctionType},
+        scanner::Scanner,
+    };
+
+    fn compile(source: &str) -> Function {
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        compiler.compile(None).unwrap().to_owned()
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let function = compile(
+            "class A {}
+            fun add(a, b) { return a + b; }
+            print add(1, 2.5) + \"!\";
+            print nil == false;",
+        );
+
+        let bytes = function.chunk.serialize();
+        let chunk = Chunk::deserialize(&bytes).unwrap();
+
+        assert_eq!(chunk.code, function.chunk.code);
+        assert_eq!(chunk.lines, function.chunk.lines);
+        assert_eq!(chunk.constants.len(), function.chunk.constants.len());
+
+        // Serializing again gives back exactly the same bytes
+        assert_eq!(chunk.serialize(), bytes);
+    }
+
+    #[test]
+    fn function_round_trip() {
+        let function = compile("fun outer(x) { fun inner() { return x; } return inner; }");
+
+        let bytes = function.serialize();
+        let copy = Function::deserialize(&bytes).unwrap();
+
+        assert_eq!(copy.serialize(), bytes);
+        match &copy.chunk.constants[1] {
+            Value::Function(outer) => {
+                assert_eq!(outer.name.as_deref(), Some("outer"));
+                assert_eq!(outer.arity, 1);
+            }
+            value => panic!("Expected a function constant, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn invalid_bytecode() {
+        let bytes = compile("print 1;").serialize();
+
+        assert!(Function::deserialize(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Function::deserialize(&trailing).is_err());
+
+        assert!(deserialize_program(&bytes).is_err());
+        assert!(deserialize_program(b"LOXC\x63").is_err());
+    }
+
+    fn program(code: &[u8], constants: Vec<Value>) -> Vec<u8> {
+        let mut function = Function::new();
+        for byte in code {
+            function.chunk.write_code(*byte, 1);
+        }
+        function.chunk.constants = constants;
+        serialize_program(&function)
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let ret = OpCode::Return as u8;
+        let cases = [
+            (program(&[99, ret], vec![]), "Unknown opcode 99 at offset 0"),
+            (
+                program(&[OpCode::Constant as u8, 1, ret], vec![Value::Nil]),
+                "Constant index out of range at offset 0",
+            ),
+            (
+                program(&[OpCode::GetUpvalue as u8, 0, ret], vec![]),
+                "Upvalue index out of range at offset 0",
+            ),
+            (
+                program(&[OpCode::Closure as u8, 0, ret], vec![Value::Nil]),
+                "Closure of a non-function at offset 0",
+            ),
+            (
+                program(&[ret, OpCode::GetLocal as u8], vec![]),
+                "Instruction at offset 1 runs past the end of the code",
+            ),
+            (
+                program(&[OpCode::Jump as u8, 0, 5, ret], vec![]),
+                "Jump at offset 0 lands outside the code",
+            ),
+            (
+                program(&[OpCode::Loop as u8, 0, 9, ret], vec![]),
+                "Jump at offset 0 lands outside the code",
+            ),
+            (
+                program(&[OpCode::Nil as u8], vec![]),
+                "Code doesn't end with a return",
+            ),
+        ];
+
+        for (bytes, message) in cases {
+            assert_eq!(deserialize_program(&bytes).err().as_deref(), Some(message));
+        }
+
+        assert!(deserialize_program(&program(&[OpCode::Nil as u8, ret], vec![])).is_ok());
+    }
+}
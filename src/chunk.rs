@@ -1,6 +1,9 @@
-use std::fmt;
+use std::{fmt, rc::Rc};
 
-use crate::value::{Class, Function, Value};
+use crate::{
+    bytecode::{self, Reader},
+    value::{Class, Function, Value},
+};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -37,6 +40,27 @@ pub enum OpCode {
     Class = 29,
     GetProperty = 30,
     SetProperty = 31,
+    BitAnd = 32,
+    BitOr = 33,
+    BitXor = 34,
+    ShiftLeft = 35,
+    ShiftRight = 36,
+    Method = 37,
+    Dup = 38,
+    Rotate = 39,
+    BuildList = 40,
+    Index = 41,
+    SetIndex = 42,
+    BuildMap = 43,
+    Println = 44,
+    Inherit = 45,
+    IsInstance = 46,
+    NotEqual = 47,
+    GreaterEqual = 48,
+    LessEqual = 49,
+    Power = 50,
+    Length = 51,
+    GetSuper = 52,
 }
 
 impl fmt::Display for OpCode {
@@ -87,6 +111,15 @@ impl fmt::Display for OpCode {
             OpCode::Print => {
                 write!(f, "OP_PRINT")
             }
+            OpCode::Println => {
+                write!(f, "OP_PRINTLN")
+            }
+            OpCode::Inherit => {
+                write!(f, "OP_INHERIT")
+            }
+            OpCode::IsInstance => {
+                write!(f, "OP_IS_INSTANCE")
+            }
             OpCode::Pop => {
                 write!(f, "OP_POP")
             }
@@ -138,6 +171,60 @@ impl fmt::Display for OpCode {
             OpCode::SetProperty => {
                 write!(f, "OP_SET_PROPERTY")
             }
+            OpCode::BitAnd => {
+                write!(f, "OP_BIT_AND")
+            }
+            OpCode::BitOr => {
+                write!(f, "OP_BIT_OR")
+            }
+            OpCode::BitXor => {
+                write!(f, "OP_BIT_XOR")
+            }
+            OpCode::ShiftLeft => {
+                write!(f, "OP_SHIFT_LEFT")
+            }
+            OpCode::ShiftRight => {
+                write!(f, "OP_SHIFT_RIGHT")
+            }
+            OpCode::Method => {
+                write!(f, "OP_METHOD")
+            }
+            OpCode::Dup => {
+                write!(f, "OP_DUP")
+            }
+            OpCode::Rotate => {
+                write!(f, "OP_ROTATE")
+            }
+            OpCode::BuildList => {
+                write!(f, "OP_BUILD_LIST")
+            }
+            OpCode::Index => {
+                write!(f, "OP_INDEX")
+            }
+            OpCode::SetIndex => {
+                write!(f, "OP_SET_INDEX")
+            }
+            OpCode::BuildMap => {
+                write!(f, "OP_BUILD_MAP")
+            }
+            OpCode::NotEqual => {
+                write!(f, "OP_NOT_EQUAL")
+            }
+            OpCode::GreaterEqual => {
+                write!(f, "OP_GREATER_EQUAL")
+            }
+            OpCode::LessEqual => {
+                write!(f, "OP_LESS_EQUAL")
+            }
+            OpCode::Power => {
+                write!(f, "OP_POWER")
+            }
+            OpCode::Length => {
+                write!(f, "OP_LENGTH")
+            }
+            OpCode::GetSuper => {
+                write!(f, "OP_GET_SUPER")
+            }
         }
     }
 }
@@ -177,6 +264,27 @@ impl OpCode {
             29 => Some(OpCode::Class),
             30 => Some(OpCode::GetProperty),
             31 => Some(OpCode::SetProperty),
+            32 => Some(OpCode::BitAnd),
+            33 => Some(OpCode::BitOr),
+            34 => Some(OpCode::BitXor),
+            35 => Some(OpCode::ShiftLeft),
+            36 => Some(OpCode::ShiftRight),
+            37 => Some(OpCode::Method),
+            38 => Some(OpCode::Dup),
+            39 => Some(OpCode::Rotate),
+            40 => Some(OpCode::BuildList),
+            41 => Some(OpCode::Index),
+            42 => Some(OpCode::SetIndex),
+            43 => Some(OpCode::BuildMap),
+            44 => Some(OpCode::Println),
+            45 => Some(OpCode::Inherit),
+            46 => Some(OpCode::IsInstance),
+            47 => Some(OpCode::NotEqual),
+            48 => Some(OpCode::GreaterEqual),
+            49 => Some(OpCode::LessEqual),
+            50 => Some(OpCode::Power),
+            51 => Some(OpCode::Length),
+            52 => Some(OpCode::GetSuper),
             _ => None,
         }
     }
@@ -185,7 +293,13 @@ impl OpCode {
 #[derive(Debug, Clone)]
 pub struct Chunk {
     pub code: Vec<u8>,
-    pub lines: Vec<usize>,
+    // (line, run length) pairs, since most lines compile to many bytes in a
+    // row. Use `line_at` to find the line for a given offset.
+    pub lines: Vec<(usize, usize)>,
+    // (start, length) of the source each byte was compiled from, for
+    // pointing at the exact token in diagnostics. Only the compiler fills
+    // these in, so chunks built any other way leave it empty.
+    pub spans: Vec<(usize, usize)>,
     pub constants: Vec<Value>,
 }
 
@@ -194,13 +308,75 @@ impl Chunk {
         Chunk {
             code: Vec::new(),
             lines: Vec::new(),
+            spans: Vec::new(),
             constants: Vec::new(),
         }
     }
 
     pub fn write_code(&mut self, code: u8, line: usize) {
         self.code.push(code);
-        self.lines.push(line);
+
+        match self.lines.last_mut() {
+            Some((last_line, run_length)) if *last_line == line => *run_length += 1,
+            _ => self.lines.push((line, 1)),
+        }
+    }
+
+    pub fn write_code_with_span(&mut self, code: u8, line: usize, span: (usize, usize)) {
+        self.write_code(code, line);
+        self.spans.push(span);
+    }
+
+    pub fn span_at(&self, offset: usize) -> Option<(usize, usize)> {
+        return self.spans.get(offset).copied();
+    }
+
+    pub fn line_at(&self, offset: usize) -> usize {
+        let mut run_start = 0;
+        for (line, run_length) in &self.lines {
+            run_start += run_length;
+            if offset < run_start {
+                return *line;
+            }
+        }
+
+        panic!(
+            "No line for offset {} in a chunk of {}",
+            offset,
+            self.code.len()
+        );
+    }
+
+    // The line of every byte in order. Walking the runs once like this is
+    // much cheaper than calling `line_at` for each offset.
+    pub fn byte_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        return self
+            .lines
+            .iter()
+            .flat_map(|(line, run_length)| std::iter::repeat_n(*line, *run_length));
+    }
+
+    // Drops everything from `length` onwards, along with the lines for it
+    pub fn truncate(&mut self, length: usize) {
+        self.code.truncate(length);
+        self.spans.truncate(length);
+
+        let mut run_start = 0;
+        for idx in 0..self.lines.len() {
+            let run_length = self.lines[idx].1;
+            if run_start + run_length >= length {
+                self.lines[idx].1 = length - run_start;
+                self.lines
+                    .truncate(if length > run_start { idx + 1 } else { idx });
+                return;
+            }
+            run_start += run_length;
+        }
+    }
+
+    pub fn write_int(&mut self, constant: i64) -> usize {
+        self.constants.push(Value::Int(constant));
+        return self.constants.len() - 1;
     }
 
     pub fn write_number(&mut self, constant: f64) -> usize {
@@ -209,17 +385,84 @@ impl Chunk {
     }
 
     pub fn write_string(&mut self, s: String) -> usize {
-        self.constants.push(Value::String(s));
+        self.constants.push(Value::String(s.into()));
         return self.constants.len() - 1;
     }
 
     pub fn write_function(&mut self, f: Function) -> usize {
-        self.constants.push(Value::Function(f));
+        self.constants.push(Value::Function(Rc::new(f)));
         return self.constants.len() - 1;
     }
 
     pub fn write_class(&mut self, c: Class) -> usize {
-        self.constants.push(Value::Class(c));
+        self.constants.push(Value::Class(Rc::new(c)));
         return self.constants.len() - 1;
     }
+
+    #[allow(dead_code)]
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        bytecode::write_chunk(&mut output, self);
+        return output;
+    }
+
+    #[allow(dead_code)]
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, String> {
+        let mut reader = Reader::new(bytes);
+        let chunk = reader.read_chunk()?;
+        reader.finish()?;
+        return Ok(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_runs() {
+        let mut chunk = Chunk::new();
+        for line in [1, 1, 1, 2, 3, 3] {
+            chunk.write_code(OpCode::Nil as u8, line);
+        }
+
+        assert_eq!(chunk.lines, vec![(1, 3), (2, 1), (3, 2)]);
+        let lines: Vec<usize> = (0..chunk.code.len())
+            .map(|offset| chunk.line_at(offset))
+            .collect();
+        assert_eq!(lines, vec![1, 1, 1, 2, 3, 3]);
+        assert_eq!(chunk.byte_lines().collect::<Vec<usize>>(), lines);
+
+        chunk.truncate(5);
+        assert_eq!(chunk.lines, vec![(1, 3), (2, 1), (3, 1)]);
+
+        chunk.truncate(3);
+        assert_eq!(chunk.lines, vec![(1, 3)]);
+
+        chunk.truncate(1);
+        assert_eq!(chunk.lines, vec![(1, 1)]);
+        assert_eq!(chunk.line_at(0), 1);
+
+        chunk.truncate(0);
+        assert!(chunk.lines.is_empty());
+    }
+
+    #[test]
+    fn spans() {
+        let mut chunk = Chunk::new();
+        chunk.write_code_with_span(OpCode::Nil as u8, 1, (0, 3));
+        chunk.write_code_with_span(OpCode::Return as u8, 1, (4, 6));
+
+        assert_eq!(chunk.span_at(0), Some((0, 3)));
+        assert_eq!(chunk.span_at(1), Some((4, 6)));
+        assert_eq!(chunk.span_at(2), None);
+
+        chunk.truncate(1);
+        assert_eq!(chunk.span_at(1), None);
+
+        // Chunks that weren't compiled have no spans at all
+        let mut chunk = Chunk::new();
+        chunk.write_code(OpCode::Return as u8, 1);
+        assert_eq!(chunk.span_at(0), None);
+    }
 }
@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::{fmt, u8};
 
 use crate::chunk::{Chunk, OpCode};
+use crate::peephole;
 use crate::scanner::{Scanner, Token, TokenType};
-use crate::value::{Class, Function};
+use crate::value::{Class, Function, Value};
 
 #[derive(Debug, Clone)]
 struct Parser {
@@ -11,6 +13,8 @@ struct Parser {
     previous: Token,
     had_error: bool,
     panic_mode: bool,
+    errors: Vec<CompileError>,
+    warnings: Vec<CompileWarning>,
 }
 
 impl Parser {
@@ -20,10 +24,76 @@ impl Parser {
             previous: Token::default(),
             had_error: false,
             panic_mode: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+
+    // Byte range of the offending token in the source
+    #[allow(dead_code)]
+    pub start: usize,
+    #[allow(dead_code)]
+    pub length: usize,
+
+    // Where the error happened, e.g. " at end" or " at foo"
+    location: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, col {}] Error{}: {}",
+            self.line, self.column, self.location, self.message
+        )
+    }
+}
+
+impl Error for CompileError {}
+
+// Something suspicious that still compiles, like a local that's never read
+#[derive(Debug, Clone)]
+pub struct CompileWarning {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Warning: {}", self.line, self.message)
+    }
+}
+
+impl CompileError {
+    // Renders the error followed by the offending source line with carets
+    // underneath the token that caused it
+    pub fn with_source(&self, source: &str) -> String {
+        let line = source
+            .lines()
+            .nth(self.line.saturating_sub(1))
+            .unwrap_or("");
+        let line_length = line.chars().count();
+
+        let column = self.column.min(line_length);
+        let carets = self.length.clamp(1, (line_length - column).max(1));
+
+        format!(
+            "{}\n    {}\n    {}{}",
+            self,
+            line,
+            " ".repeat(column),
+            "^".repeat(carets)
+        )
+    }
+}
+
 #[derive(Clone, Copy)]
 enum Precedence {
     None,
@@ -32,8 +102,13 @@ enum Precedence {
     And,        // and
     Equality,   // == !=
     Comparison, // < > <= >=
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
+    Shift,      // << >>
     Term,       // + -
     Factor,     // * /
+    Exponent,   // **
     Unary,      // ! -
     Call,       // . ()
     Primary,
@@ -48,10 +123,15 @@ impl Precedence {
             3 => Precedence::And,
             4 => Precedence::Equality,
             5 => Precedence::Comparison,
-            6 => Precedence::Term,
-            7 => Precedence::Factor,
-            8 => Precedence::Unary,
-            9 => Precedence::Call,
+            6 => Precedence::BitOr,
+            7 => Precedence::BitXor,
+            8 => Precedence::BitAnd,
+            9 => Precedence::Shift,
+            10 => Precedence::Term,
+            11 => Precedence::Factor,
+            12 => Precedence::Exponent,
+            13 => Precedence::Unary,
+            14 => Precedence::Call,
             _ => Precedence::Primary,
         }
     }
@@ -71,17 +151,30 @@ struct Local {
     name: Token,
     depth: Option<u16>,
     is_captured: bool,
+    is_const: bool,
+    is_read: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
 struct Upvalue {
     index: u8,
     is_local: bool,
+    is_const: bool,
+}
+
+// Tracks the class body currently being compiled. These nest so that a
+// class declared inside a method still knows about the outer class.
+#[derive(Debug, Clone)]
+struct ClassCompiler {
+    enclosing: Option<Box<ClassCompiler>>,
+    has_superclass: bool,
 }
 
 #[derive(Clone, Copy)]
 pub enum FunctionType {
     Function,
+    Initializer,
+    Method,
     Script,
 }
 
@@ -91,6 +184,12 @@ impl fmt::Display for FunctionType {
             FunctionType::Function => {
                 write!(f, "Function")
             }
+            FunctionType::Initializer => {
+                write!(f, "Initializer")
+            }
+            FunctionType::Method => {
+                write!(f, "Method")
+            }
             FunctionType::Script => {
                 write!(f, "Script")
             }
@@ -114,6 +213,15 @@ pub struct Compiler {
     function: Function,
     function_type: FunctionType,
     upvalues: [Option<Upvalue>; u8::MAX as usize + 1],
+
+    current_class: Option<ClassCompiler>,
+
+    // Names of globals declared with `const`. Nested compilers start with a
+    // copy so functions can't assign to constants declared before them.
+    const_globals: HashSet<String>,
+
+    // Where the left operand of the infix expression being compiled starts
+    operand_start: usize,
 }
 
 impl Compiler {
@@ -135,11 +243,18 @@ impl Compiler {
                 name: Token::default(),
                 depth: Some(0),
                 is_captured: false,
+                is_const: false,
+                is_read: false,
             }; u8::MAX as usize + 1],
 
             function: Function::new(),
             function_type,
             upvalues: [None; u8::MAX as usize + 1],
+
+            current_class: None,
+
+            const_globals: HashSet::new(),
+            operand_start: 0,
         };
 
         // Most of these fields are already initialized to these values
@@ -150,6 +265,15 @@ impl Compiler {
         compiler.locals[0].is_captured = false;
         compiler.local_count += 1;
 
+        // Methods keep the instance they were called on in slot 0
+        match function_type {
+            FunctionType::Method | FunctionType::Initializer => {
+                compiler.locals[0].name.token_type = TokenType::This;
+                compiler.locals[0].name.length = 4;
+            }
+            _ => {}
+        }
+
         compiler.precedence_map.insert(
             TokenType::LeftParen,
             ParseRule {
@@ -169,7 +293,7 @@ impl Compiler {
         compiler.precedence_map.insert(
             TokenType::LeftBrace,
             ParseRule {
-                prefix: None,
+                prefix: Some(Compiler::map),
                 infix: None,
                 precedence: Precedence::None,
             },
@@ -182,6 +306,30 @@ impl Compiler {
                 precedence: Precedence::None,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::LeftBracket,
+            ParseRule {
+                prefix: Some(Compiler::list),
+                infix: Some(Compiler::index),
+                precedence: Precedence::Call,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::RightBracket,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::Colon,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Comma,
             ParseRule {
@@ -238,6 +386,38 @@ impl Compiler {
                 precedence: Precedence::Factor,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::StarStar,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::Exponent,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::Ampersand,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::BitAnd,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::Pipe,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::BitOr,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::Caret,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::BitXor,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Bang,
             ParseRule {
@@ -254,6 +434,38 @@ impl Compiler {
                 precedence: Precedence::Equality,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::PlusEqual,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::MinusEqual,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::StarEqual,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::SlashEqual,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Equal,
             ParseRule {
@@ -302,6 +514,22 @@ impl Compiler {
                 precedence: Precedence::Comparison,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::LessLess,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::Shift,
+            },
+        );
+        compiler.precedence_map.insert(
+            TokenType::GreaterGreater,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::Shift,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Identifier,
             ParseRule {
@@ -342,6 +570,14 @@ impl Compiler {
                 precedence: Precedence::None,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::Do,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Else,
             ParseRule {
@@ -382,6 +618,14 @@ impl Compiler {
                 precedence: Precedence::None,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::Is,
+            ParseRule {
+                prefix: None,
+                infix: Some(Compiler::is_),
+                precedence: Precedence::Comparison,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Nil,
             ParseRule {
@@ -406,6 +650,14 @@ impl Compiler {
                 precedence: Precedence::None,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::Println,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Return,
             ParseRule {
@@ -417,7 +669,7 @@ impl Compiler {
         compiler.precedence_map.insert(
             TokenType::Super,
             ParseRule {
-                prefix: None,
+                prefix: Some(Compiler::super_),
                 infix: None,
                 precedence: Precedence::None,
             },
@@ -425,7 +677,7 @@ impl Compiler {
         compiler.precedence_map.insert(
             TokenType::This,
             ParseRule {
-                prefix: None,
+                prefix: Some(Compiler::this_),
                 infix: None,
                 precedence: Precedence::None,
             },
@@ -438,6 +690,14 @@ impl Compiler {
                 precedence: Precedence::None,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::Const,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Var,
             ParseRule {
@@ -454,6 +714,14 @@ impl Compiler {
                 precedence: Precedence::None,
             },
         );
+        compiler.precedence_map.insert(
+            TokenType::Comment,
+            ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::None,
+            },
+        );
         compiler.precedence_map.insert(
             TokenType::Error,
             ParseRule {
@@ -474,6 +742,26 @@ impl Compiler {
         return compiler;
     }
 
+    pub fn errors(&self) -> &Vec<CompileError> {
+        &self.parser.errors
+    }
+
+    pub fn warnings(&self) -> &Vec<CompileWarning> {
+        &self.parser.warnings
+    }
+
+    fn warn_unused_local(&mut self, local: Local) {
+        if local.is_read {
+            return;
+        }
+
+        let lexeme = &self.scanner.source[local.name.start..(local.name.start + local.name.length)];
+        self.parser.warnings.push(CompileWarning {
+            message: format!("unused variable '{}'", lexeme),
+            line: local.name.line,
+        });
+    }
+
     pub fn current_chunk(&mut self) -> &mut Chunk {
         return &mut self.function.chunk;
     }
@@ -489,18 +777,23 @@ impl Compiler {
         }
         self.parser.panic_mode = true;
 
-        print!("[line {}] Error", token.line);
-
-        if token.token_type as u8 == TokenType::Eof as u8 {
-            print!(" at end");
+        let location = if token.token_type as u8 == TokenType::Eof as u8 {
+            String::from(" at end")
         } else if token.token_type as u8 == TokenType::Error as u8 {
+            String::new()
         } else {
             let source_string = &self.scanner.source[token.start..(token.start + token.length)];
-            print!(" at {}", source_string);
-        }
-
-        println!(": {}", message);
+            format!(" at {}", source_string)
+        };
 
+        self.parser.errors.push(CompileError {
+            message: String::from(message),
+            line: token.line,
+            column: token.column,
+            start: token.start,
+            length: token.length,
+            location,
+        });
         self.parser.had_error = true;
     }
 
@@ -519,7 +812,16 @@ impl Compiler {
             self.parser.current = self.scanner.scan_token();
 
             match self.parser.current.token_type {
-                TokenType::Error => self.error_at_current("error"),
+                TokenType::Error => {
+                    let message = match self.parser.current.error {
+                        Some(error) => error.to_string(),
+                        None => String::from("Unexpected token."),
+                    };
+                    self.error_at_current(message.as_str());
+                }
+                // Only scanners that opt in produce these and the
+                // compiler has no use for them
+                TokenType::Comment => {}
                 _ => break,
             }
         }
@@ -530,6 +832,10 @@ impl Compiler {
         self.emit_byte(byte2);
     }
 
+    fn emit_dup(&mut self) {
+        self.emit_byte(OpCode::Dup as u8);
+    }
+
     fn emit_jump(&mut self, instruction: OpCode) -> usize {
         self.emit_byte(instruction as u8);
         self.emit_byte(0xff);
@@ -540,8 +846,12 @@ impl Compiler {
     }
 
     fn emit_byte(&mut self, byte: u8) {
-        let line = self.parser.previous.line;
-        self.current_chunk().write_code(byte, line);
+        let previous = self.parser.previous;
+        self.current_chunk().write_code_with_span(
+            byte,
+            previous.line,
+            (previous.start, previous.length),
+        );
     }
 
     fn patch_jump(&mut self, offset: usize) {
@@ -570,12 +880,17 @@ impl Compiler {
     }
 
     fn emit_return(&mut self) {
-        self.emit_byte(OpCode::Nil as u8);
+        // Initializers always hand back the instance they just set up
+        match self.function_type {
+            FunctionType::Initializer => self.emit_bytes(OpCode::GetLocal as u8, 0),
+            _ => self.emit_byte(OpCode::Nil as u8),
+        }
         self.emit_byte(OpCode::Return as u8);
     }
 
     fn end_compiler(&mut self) -> &mut Function {
         self.emit_return();
+        peephole::optimize(&mut self.function.chunk);
         return &mut self.function;
     }
 
@@ -585,13 +900,12 @@ impl Compiler {
 
     fn end_scope(&mut self) {
         self.scope_depth -= 1;
-        for i in 0..self.local_count as usize {
-            println!("{:?}", self.locals[i]);
-        }
 
         while self.local_count > 0
             && self.locals[self.local_count as usize - 1].depth.unwrap() > self.scope_depth
         {
+            self.warn_unused_local(self.locals[self.local_count as usize - 1]);
+
             if self.locals[self.local_count as usize - 1].is_captured {
                 self.emit_byte(OpCode::CloseUpvalue as u8);
             } else {
@@ -640,13 +954,50 @@ impl Compiler {
 
         let start = self.parser.previous.start + 1;
         let end = start + self.parser.previous.length - 2;
-        let lexeme = self.scanner.source[start..end].to_owned();
+        let lexeme = Compiler::unescape(&self.scanner.source[start..end]);
 
-        let constant_index = self.current_chunk().write_string(String::from(lexeme));
+        let constant_index = self.current_chunk().write_string(lexeme);
         self.emit_byte(constant_index as u8);
     }
 
+    // Backslash escapes in a string literal. Anything that isn't a known
+    // escape is left as it was written.
+    fn unescape(lexeme: &str) -> String {
+        let mut output = String::with_capacity(lexeme.len());
+        let mut chars = lexeme.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                output.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('n') => output.push('\n'),
+                Some('t') => output.push('\t'),
+                Some('r') => output.push('\r'),
+                Some('"') => output.push('"'),
+                Some('\\') => output.push('\\'),
+                Some(other) => {
+                    output.push('\\');
+                    output.push(other);
+                }
+                None => output.push('\\'),
+            }
+        }
+
+        return output;
+    }
+
     fn identifiers_equal(&mut self, a: Token, b: Token) -> bool {
+        // `this` in slot 0 doesn't point anywhere in the source, so it can
+        // only ever match another `this`
+        if a.token_type as u8 == TokenType::This as u8
+            || b.token_type as u8 == TokenType::This as u8
+        {
+            return a.token_type as u8 == b.token_type as u8;
+        }
+
         if a.length != b.length {
             return false;
         }
@@ -663,12 +1014,14 @@ impl Compiler {
 
         let local_index = self.resolve_local(name);
         let index: usize;
+        let is_const: bool;
 
         // if the index exists, then the variable is a local
         // otherwise, it's a global
         match local_index {
             Some(idx) => {
                 index = idx;
+                is_const = self.locals[idx].is_const;
 
                 get_operation = OpCode::GetLocal;
                 set_operation = OpCode::SetLocal;
@@ -680,6 +1033,7 @@ impl Compiler {
                     None => {
                         let lexeme =
                             self.scanner.source[name.start..(name.start + name.length)].to_owned();
+                        is_const = self.const_globals.contains(&lexeme);
                         index = self.current_chunk().write_string(lexeme);
 
                         get_operation = OpCode::GetGlobal;
@@ -687,6 +1041,7 @@ impl Compiler {
                     }
                     Some(idx) => {
                         index = idx;
+                        is_const = self.upvalues[idx].unwrap().is_const;
 
                         get_operation = OpCode::GetUpvalue;
                         set_operation = OpCode::SetUpvalue;
@@ -696,6 +1051,11 @@ impl Compiler {
         }
 
         if can_assign && self.match_token(TokenType::Equal) {
+            if is_const {
+                let lexeme = &self.scanner.source[name.start..(name.start + name.length)];
+                self.error(format!("Cannot assign to constant '{}'.", lexeme).as_str());
+            }
+
             self.expression();
             self.emit_bytes(set_operation as u8, index as u8);
         } else {
@@ -707,19 +1067,71 @@ impl Compiler {
         self.named_variable(self.parser.previous, can_assign)
     }
 
+    fn this_(&mut self, _can_assign: bool) {
+        if self.current_class.is_none() {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+
+        self.variable(false);
+    }
+
+    // `value is Class` is true for instances of the class or any subclass
+    fn is_(&mut self, _can_assign: bool) {
+        self.parse_precedence(Precedence::from_u8(Precedence::Comparison as u8 + 1));
+        self.emit_byte(OpCode::IsInstance as u8);
+    }
+
+    // `super.name` is the superclass's method bound to `this`
+    fn super_(&mut self, _can_assign: bool) {
+        match &self.current_class {
+            Some(class) if class.has_superclass => {}
+            _ => {
+                self.error("Can't use 'super' outside of a class with a superclass.");
+                return;
+            }
+        }
+
+        let mut this = self.parser.previous;
+        this.token_type = TokenType::This;
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenType::Identifier, "Expect superclass method name.");
+        let name = self.parser.previous;
+        let lexeme = self.scanner.source[name.start..(name.start + name.length)].to_owned();
+        let index_of_name = self.current_chunk().write_string(lexeme);
+
+        self.named_variable(this, false);
+        self.emit_bytes(OpCode::GetSuper as u8, index_of_name as u8);
+    }
+
     fn number(&mut self, _can_assign: bool) {
         self.emit_byte(OpCode::Constant as u8);
 
         let lexeme = &self.scanner.source[self.parser.previous.start
             ..(self.parser.previous.start + self.parser.previous.length)];
 
-        match lexeme.parse::<f64>() {
-            Ok(value) => {
-                let constant_index = self.current_chunk().write_number(value);
+        // Literals without a decimal point are ints, everything else is a
+        // float. So are ints too big for an i64, rather than an error.
+        let int = match lexeme.contains('.') {
+            true => None,
+            false => lexeme.parse::<i64>().ok(),
+        };
+
+        match int {
+            Some(value) => {
+                let constant_index = self.current_chunk().write_int(value);
                 self.emit_byte(constant_index as u8);
             }
-            Err(e) => self
-                .error(format!("couldn't parse {} into number, got error: {}", lexeme, e).as_str()),
+            None => match lexeme.parse::<f64>() {
+                Ok(value) => {
+                    let constant_index = self.current_chunk().write_number(value);
+                    self.emit_byte(constant_index as u8);
+                }
+                Err(e) => self.error(
+                    format!("couldn't parse {} into number, got error: {}", lexeme, e).as_str(),
+                ),
+            },
         }
     }
 
@@ -739,6 +1151,7 @@ impl Compiler {
 
     fn binary(&mut self, _can_assign: bool) {
         let op_type = self.parser.previous.token_type;
+        let left_start = self.operand_start;
 
         let parse_rule = match self.precedence_map.get(&op_type).cloned() {
             Some(pr) => pr,
@@ -748,47 +1161,234 @@ impl Compiler {
             }
         };
 
-        self.parse_precedence(Precedence::from_u8(parse_rule.precedence as u8 + 1));
+        // `**` is right associative, so its right operand can contain another
+        // `**` at the same precedence: `2 ** 3 ** 2` is `2 ** (3 ** 2)`
+        let right_precedence = match op_type {
+            TokenType::StarStar => parse_rule.precedence,
+            _ => Precedence::from_u8(parse_rule.precedence as u8 + 1),
+        };
+
+        let right_start = self.current_chunk().code.len();
+        self.parse_precedence(right_precedence);
+
+        if self.fold_constants(op_type, left_start, right_start) {
+            return;
+        }
+
+        if Compiler::is_comparison(op_type)
+            && Compiler::is_comparison(self.parser.current.token_type)
+        {
+            self.chained_comparison(op_type);
+            return;
+        }
 
         match op_type {
             TokenType::Plus => self.emit_byte(OpCode::Add as u8),
             TokenType::Slash => self.emit_byte(OpCode::Divide as u8),
             TokenType::Star => self.emit_byte(OpCode::Multiply as u8),
+            TokenType::StarStar => self.emit_byte(OpCode::Power as u8),
             TokenType::Minus => self.emit_byte(OpCode::Subtract as u8),
             TokenType::BangEqual => self.emit_bytes(OpCode::Equal as u8, OpCode::Not as u8),
             TokenType::EqualEqual => self.emit_byte(OpCode::Equal as u8),
-            TokenType::Greater => self.emit_byte(OpCode::Greater as u8),
-            TokenType::GreaterEqual => self.emit_bytes(OpCode::Less as u8, OpCode::Not as u8),
-            TokenType::Less => self.emit_byte(OpCode::Less as u8),
-            TokenType::LessEqual => self.emit_bytes(OpCode::Greater as u8, OpCode::Not as u8),
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => self.emit_comparison(op_type),
+            TokenType::Ampersand => self.emit_byte(OpCode::BitAnd as u8),
+            TokenType::Pipe => self.emit_byte(OpCode::BitOr as u8),
+            TokenType::Caret => self.emit_byte(OpCode::BitXor as u8),
+            TokenType::LessLess => self.emit_byte(OpCode::ShiftLeft as u8),
+            TokenType::GreaterGreater => self.emit_byte(OpCode::ShiftRight as u8),
             _ => println!("need to implement binary opcode {:?}", op_type),
         }
     }
 
-    fn grouping(&mut self, _can_assign: bool) {
-        self.expression();
-        self.consume(TokenType::RightParen, "Expect ')' after expression.");
+    // The constant an operand compiled to, if it's nothing but a number
+    // literal taking up the code from `start` to `end`
+    fn constant_operand(&mut self, start: usize, end: usize) -> Option<Value> {
+        let chunk = self.current_chunk();
+        if end - start != 2 || chunk.code[start] != OpCode::Constant as u8 {
+            return None;
+        }
+
+        match &chunk.constants[chunk.code[start + 1] as usize] {
+            value @ (Value::Int(_) | Value::Number(_)) => return Some(value.clone()),
+            _ => return None,
+        }
     }
 
-    fn dot(&mut self, can_assign: bool) {
-        self.consume(TokenType::Identifier, "Expect property name after '.'.");
-        let lexeme = self.scanner.source[self.parser.previous.start
-            ..(self.parser.previous.start + self.parser.previous.length)]
-            .to_owned();
-        let index_of_name = self.current_chunk().write_string(lexeme);
+    // Arithmetic on two number literals is done here instead of at runtime.
+    // Anything that would be a runtime error, like an overflow or dividing
+    // by zero, is left for the VM to report.
+    fn fold_constants(
+        &mut self,
+        op_type: TokenType,
+        left_start: usize,
+        right_start: usize,
+    ) -> bool {
+        let end = self.current_chunk().code.len();
+        let (Some(a), Some(b)) = (
+            self.constant_operand(left_start, right_start),
+            self.constant_operand(right_start, end),
+        ) else {
+            return false;
+        };
 
-        if can_assign && self.match_token(TokenType::Equal) {
-            self.expression();
-            self.emit_bytes(OpCode::SetProperty as u8, index_of_name as u8);
-        } else {
-            self.emit_bytes(OpCode::GetProperty as u8, index_of_name as u8);
+        let folded = match (a, b) {
+            (Value::Int(a), Value::Int(b)) => match op_type {
+                TokenType::Plus => a.checked_add(b),
+                TokenType::Minus => a.checked_sub(b),
+                TokenType::Star => a.checked_mul(b),
+                TokenType::Slash => a.checked_div(b),
+                _ => None,
+            }
+            .map(Value::Int),
+            (a, b) => {
+                let as_float = |value: Value| match value {
+                    Value::Int(n) => n as f64,
+                    Value::Number(n) => n,
+                    _ => unreachable!(),
+                };
+                let (a, b) = (as_float(a), as_float(b));
+                match op_type {
+                    TokenType::Plus => Some(Value::Number(a + b)),
+                    TokenType::Minus => Some(Value::Number(a - b)),
+                    TokenType::Star => Some(Value::Number(a * b)),
+                    TokenType::Slash if b != 0.0 => Some(Value::Number(a / b)),
+                    _ => None,
+                }
+            }
+        };
+
+        let Some(folded) = folded else {
+            return false;
+        };
+
+        // The operands are normally the last two constants written, in which
+        // case they aren't needed anymore
+        let chunk = self.current_chunk();
+        let last_constants = (
+            chunk.code[left_start + 1] as usize,
+            chunk.code[right_start + 1] as usize,
+        );
+        if last_constants == (chunk.constants.len() - 2, chunk.constants.len() - 1) {
+            chunk.constants.truncate(chunk.constants.len() - 2);
         }
-    }
+        chunk.truncate(left_start);
+        chunk.constants.push(folded);
 
-    fn parse_precedence(&mut self, precedence: Precedence) {
-        self.advance();
+        let constant_index = chunk.constants.len() - 1;
+        self.emit_bytes(OpCode::Constant as u8, constant_index as u8);
 
-        let parse_rule = match self
+        return true;
+    }
+
+    fn is_comparison(token_type: TokenType) -> bool {
+        matches!(
+            token_type,
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual
+        )
+    }
+
+    fn emit_comparison(&mut self, op_type: TokenType) {
+        match op_type {
+            TokenType::Greater => self.emit_byte(OpCode::Greater as u8),
+            TokenType::GreaterEqual => self.emit_byte(OpCode::GreaterEqual as u8),
+            TokenType::Less => self.emit_byte(OpCode::Less as u8),
+            TokenType::LessEqual => self.emit_byte(OpCode::LessEqual as u8),
+            _ => unreachable!("{:?} is not a comparison", op_type),
+        }
+    }
+
+    // `a < b < c` means `a < b and b < c` with `b` only evaluated once, so
+    // every operand shared by two comparisons is duplicated and tucked
+    // underneath its left hand side before the first comparison runs
+    fn chained_comparison(&mut self, first_op_type: TokenType) {
+        let mut false_jumps = Vec::new();
+        let mut op_type = first_op_type;
+
+        while Compiler::is_comparison(self.parser.current.token_type) {
+            // [a b] -> [b a b] -> [b (a < b)]
+            self.emit_dup();
+            self.emit_byte(OpCode::Rotate as u8);
+            self.emit_comparison(op_type);
+
+            false_jumps.push(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_byte(OpCode::Pop as u8);
+
+            self.advance();
+            op_type = self.parser.previous.token_type;
+            self.parse_precedence(Precedence::from_u8(Precedence::Comparison as u8 + 1));
+        }
+
+        self.emit_comparison(op_type);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        // A failed comparison leaves its shared operand underneath the
+        // result, so both get cleared out before pushing the answer
+        for jump in false_jumps {
+            self.patch_jump(jump);
+        }
+        self.emit_byte(OpCode::Pop as u8);
+        self.emit_byte(OpCode::Pop as u8);
+        self.emit_byte(OpCode::False as u8);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after expression.");
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenType::Identifier, "Expect property name after '.'.");
+        let lexeme = self.scanner.source[self.parser.previous.start
+            ..(self.parser.previous.start + self.parser.previous.length)]
+            .to_owned();
+        let index_of_name = self.current_chunk().write_string(lexeme);
+
+        // Only the last `.` in a chain can assign. The earlier ones are parsed
+        // as the left operand of the next one, where `can_assign` is false,
+        // so `a.b.c = 1` gets `a.b` and then sets `c` on it.
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_bytes(OpCode::SetProperty as u8, index_of_name as u8);
+        } else if let Some(op) = self.compound_assignment(can_assign) {
+            // Keep a copy of the instance around for the set
+            self.emit_dup();
+            self.emit_bytes(OpCode::GetProperty as u8, index_of_name as u8);
+            self.expression();
+            self.emit_byte(op as u8);
+            self.emit_bytes(OpCode::SetProperty as u8, index_of_name as u8);
+        } else {
+            self.emit_bytes(OpCode::GetProperty as u8, index_of_name as u8);
+        }
+    }
+
+    // Matches `+=` and friends, handing back the arithmetic they stand for
+    fn compound_assignment(&mut self, can_assign: bool) -> Option<OpCode> {
+        if !can_assign {
+            return None;
+        }
+
+        let op = match self.parser.current.token_type {
+            TokenType::PlusEqual => OpCode::Add,
+            TokenType::MinusEqual => OpCode::Subtract,
+            TokenType::StarEqual => OpCode::Multiply,
+            TokenType::SlashEqual => OpCode::Divide,
+            _ => return None,
+        };
+
+        self.advance();
+        return Some(op);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        let operand_start = self.current_chunk().code.len();
+        self.advance();
+
+        let parse_rule = match self
             .precedence_map
             .get(&self.parser.previous.token_type)
             .cloned()
@@ -839,12 +1439,15 @@ impl Compiler {
 
             self.advance();
 
+            self.operand_start = operand_start;
             match parse_rule.infix {
                 Some(infix_func) => infix_func(self, can_assign),
                 _ => return,
             }
 
-            if can_assign && self.match_token(TokenType::Equal) {
+            if can_assign
+                && (self.match_token(TokenType::Equal) || self.compound_assignment(true).is_some())
+            {
                 self.error("Invalid assignment target.");
             }
         }
@@ -907,11 +1510,38 @@ impl Compiler {
         self.emit_byte(OpCode::Pop as u8);
     }
 
+    // The condition comes after the body, so the body always runs once
+    fn do_while_statement(&mut self) {
+        let loop_start = self.current_chunk().code.len();
+
+        self.statement();
+
+        self.consume(TokenType::While, "Expect 'while' after do body.");
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        self.consume(TokenType::Semicolon, "Expect ';' after do while condition.");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop as u8);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::Pop as u8);
+    }
+
     fn for_statement(&mut self) {
         self.begin_scope();
 
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
 
+        if self.check(TokenType::Identifier) && self.peek_token_type() as u8 == TokenType::In as u8
+        {
+            self.for_in_statement();
+            self.end_scope();
+            return;
+        }
+
         if self.match_token(TokenType::Semicolon) {
             // no initializer
         } else if self.match_token(TokenType::Var) {
@@ -958,6 +1588,85 @@ impl Compiler {
         self.end_scope();
     }
 
+    // The token after `current`, without consuming anything
+    fn peek_token_type(&mut self) -> TokenType {
+        let checkpoint = self.scanner.checkpoint();
+        let token_type = loop {
+            let token = self.scanner.scan_token();
+            if token.token_type as u8 != TokenType::Comment as u8 {
+                break token.token_type;
+            }
+        };
+        self.scanner.restore(checkpoint);
+
+        return token_type;
+    }
+
+    // A local the user can't name, for bookkeeping the compiler does itself.
+    // Identifiers are never empty, so nothing resolves to it.
+    fn add_hidden_local(&mut self) -> u8 {
+        let mut name = self.parser.previous;
+        name.length = 0;
+
+        self.add_local(name);
+        self.mark_initialized();
+        self.locals[self.local_count as usize - 1].is_read = true;
+
+        return self.local_count - 1;
+    }
+
+    // `for (item in list) body` runs like
+    //
+    //     var <list> = list;
+    //     for (var <index> = 0; <index> < len(<list>); <index> = <index> + 1) {
+    //         var item = <list>[<index>];
+    //         body
+    //     }
+    fn for_in_statement(&mut self) {
+        self.advance();
+        let name = self.parser.previous;
+        self.consume(TokenType::In, "Expect 'in' after loop variable.");
+
+        self.expression();
+        let list_slot = self.add_hidden_local();
+
+        let constant_index = self.current_chunk().write_int(0);
+        self.emit_bytes(OpCode::Constant as u8, constant_index as u8);
+        let index_slot = self.add_hidden_local();
+
+        self.consume(TokenType::RightParen, "Expect ')' after for-in collection.");
+
+        let loop_start = self.current_chunk().code.len();
+        self.emit_bytes(OpCode::GetLocal as u8, index_slot);
+        self.emit_bytes(OpCode::GetLocal as u8, list_slot);
+        self.emit_byte(OpCode::Length as u8);
+        self.emit_byte(OpCode::Less as u8);
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop as u8);
+
+        self.begin_scope();
+        self.emit_bytes(OpCode::GetLocal as u8, list_slot);
+        self.emit_bytes(OpCode::GetLocal as u8, index_slot);
+        self.emit_byte(OpCode::Index as u8);
+        self.add_local(name);
+        self.mark_initialized();
+
+        self.statement();
+        self.end_scope();
+
+        self.emit_bytes(OpCode::GetLocal as u8, index_slot);
+        let constant_index = self.current_chunk().write_int(1);
+        self.emit_bytes(OpCode::Constant as u8, constant_index as u8);
+        self.emit_byte(OpCode::Add as u8);
+        self.emit_bytes(OpCode::SetLocal as u8, index_slot);
+        self.emit_byte(OpCode::Pop as u8);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::Pop as u8); // pop the condition
+    }
+
     fn return_statement(&mut self) {
         match self.function_type {
             FunctionType::Script => {
@@ -969,6 +1678,13 @@ impl Compiler {
         if self.match_token(TokenType::Semicolon) {
             self.emit_return();
         } else {
+            match self.function_type {
+                FunctionType::Initializer => {
+                    self.error("Can't return a value from an initializer.");
+                }
+                _ => {}
+            }
+
             self.expression();
             self.consume(TokenType::Semicolon, "Expect ';' after return value.");
             self.emit_byte(OpCode::Return as u8);
@@ -1042,6 +1758,8 @@ impl Compiler {
         self.patch_jump(end_jump);
     }
 
+    // There's no JumpIfTrue, so a falsey left operand jumps over the
+    // unconditional jump that skips the right operand
     fn or_(&mut self, _can_assign: bool) {
         let else_jump = self.emit_jump(OpCode::JumpIfFalse);
         let end_jump = self.emit_jump(OpCode::Jump);
@@ -1054,17 +1772,49 @@ impl Compiler {
     }
 
     fn var_declaration(&mut self) {
-        let global_index = self.parse_variable("Expect variable name.");
+        // `var a = 1, b = 2;` declares each variable in turn, so later
+        // initializers can see the earlier variables
+        loop {
+            let global_index = self.parse_variable("Expect variable name.");
 
-        if self.match_token(TokenType::Equal) {
-            self.expression();
-        } else {
-            self.emit_byte(OpCode::Nil as u8);
+            if self.match_token(TokenType::Equal) {
+                self.expression();
+            } else {
+                self.emit_byte(OpCode::Nil as u8);
+            }
+
+            self.define_variable(global_index);
+
+            if !self.match_token(TokenType::Comma) {
+                break;
+            }
         }
+
         self.consume(
             TokenType::Semicolon,
             "Expect ';' after variable declaration.",
         );
+    }
+
+    // Constants work like variables except that they must be initialized and
+    // can't be assigned to afterwards
+    fn const_declaration(&mut self) {
+        let global_index = self.parse_variable("Expect constant name.");
+        let name = self.parser.previous;
+
+        self.consume(TokenType::Equal, "Expect '=' after constant name.");
+        self.expression();
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        );
+
+        if self.scope_depth > 0 {
+            self.locals[self.local_count as usize - 1].is_const = true;
+        } else {
+            let lexeme = self.scanner.source[name.start..(name.start + name.length)].to_owned();
+            self.const_globals.insert(lexeme);
+        }
 
         self.define_variable(global_index);
     }
@@ -1075,6 +1825,8 @@ impl Compiler {
             let local = self.locals[idx];
 
             if self.identifiers_equal(name, local.name) {
+                self.locals[idx].is_read = true;
+
                 match local.depth {
                     None => {
                         self.error("Can't read local variable in its own initializer");
@@ -1087,7 +1839,7 @@ impl Compiler {
         return None;
     }
 
-    fn add_upvalue(&mut self, index: usize, is_local: bool) -> usize {
+    fn add_upvalue(&mut self, index: usize, is_local: bool, is_const: bool) -> usize {
         let upvalue_count = self.function.upvalue_count as usize;
 
         for idx in 0..upvalue_count {
@@ -1112,6 +1864,7 @@ impl Compiler {
         self.upvalues[upvalue_count] = Some(Upvalue {
             is_local,
             index: index as u8,
+            is_const,
         });
 
         self.function.upvalue_count += 1;
@@ -1130,12 +1883,16 @@ impl Compiler {
 
                         match upvalue {
                             None => return None, // must be a global var or an error
-                            Some(idx) => return Some(self.add_upvalue(idx, false)),
+                            Some(idx) => {
+                                let is_const = compiler.upvalues[idx].unwrap().is_const;
+                                return Some(self.add_upvalue(idx, false, is_const));
+                            }
                         }
                     }
                     Some(idx) => {
                         compiler.locals[idx].is_captured = true;
-                        return Some(self.add_upvalue(idx, true));
+                        let is_const = compiler.locals[idx].is_const;
+                        return Some(self.add_upvalue(idx, true, is_const));
                     }
                 }
             }
@@ -1152,11 +1909,18 @@ impl Compiler {
                 }
 
                 self.expression();
-                arg_count += 1;
+                arg_count = arg_count.saturating_add(1);
 
                 if !self.match_token(TokenType::Comma) {
                     break;
                 }
+
+                // Unlike list and map literals, calls don't allow a trailing
+                // comma so that arguments read the same as parameters
+                if self.check(TokenType::RightParen) {
+                    self.error_at_current("Expect expression after ',' in arguments.");
+                    break;
+                }
             }
         }
 
@@ -1169,6 +1933,62 @@ impl Compiler {
         self.emit_bytes(OpCode::Call as u8, arg_count);
     }
 
+    fn list(&mut self, _can_assign: bool) {
+        let mut item_count: u8 = 0;
+
+        while !self.check(TokenType::RightBracket) {
+            if item_count == 255 {
+                self.error("Can't have more than 255 items in a list literal.");
+            }
+
+            self.expression();
+            item_count = item_count.saturating_add(1);
+
+            if !self.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list items.");
+        self.emit_bytes(OpCode::BuildList as u8, item_count);
+    }
+
+    // Blocks are only ever parsed as statements, so a `{` showing up where an
+    // expression is expected is always a map literal
+    fn map(&mut self, _can_assign: bool) {
+        let mut entry_count: u8 = 0;
+
+        while !self.check(TokenType::RightBrace) {
+            if entry_count == 255 {
+                self.error("Can't have more than 255 entries in a map literal.");
+            }
+
+            self.expression();
+            self.consume(TokenType::Colon, "Expect ':' after map key.");
+            self.expression();
+            entry_count = entry_count.saturating_add(1);
+
+            if !self.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+        self.emit_bytes(OpCode::BuildMap as u8, entry_count);
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_byte(OpCode::SetIndex as u8);
+        } else {
+            self.emit_byte(OpCode::Index as u8);
+        }
+    }
+
     fn add_local(&mut self, name: Token) {
         if self.local_count as usize == u8::MAX as usize + 1 {
             self.error("Too many local variables in block");
@@ -1177,6 +1997,8 @@ impl Compiler {
 
         self.locals[self.local_count as usize].name = name;
         self.locals[self.local_count as usize].depth = None;
+        self.locals[self.local_count as usize].is_const = false;
+        self.locals[self.local_count as usize].is_read = false;
 
         self.local_count += 1;
     }
@@ -1189,9 +2011,11 @@ impl Compiler {
         );
 
         compiler.patch_parser(self.parser.previous, self.parser.current);
+        compiler.current_class = self.current_class.clone();
+        compiler.const_globals = self.const_globals.clone();
 
         match function_type {
-            FunctionType::Function => {
+            FunctionType::Function | FunctionType::Method | FunctionType::Initializer => {
                 compiler.function.name = Some(
                     compiler.scanner.source[compiler.parser.previous.start
                         ..(compiler.parser.previous.start + compiler.parser.previous.length)]
@@ -1202,40 +2026,60 @@ impl Compiler {
         }
         compiler.begin_scope();
 
-        compiler.consume(TokenType::LeftParen, "Expect '(' after function name.");
-        if !compiler.check(TokenType::RightParen) {
-            loop {
-                if compiler.function.arity == 255 {
-                    self.error_at_current("Can't have more than 255 parameters.");
-                }
-                compiler.function.arity += 1;
+        // A method without a parameter list is a getter, which runs as soon
+        // as the property is accessed
+        if let FunctionType::Method = function_type {
+            compiler.function.is_getter = !compiler.check(TokenType::LeftParen);
+        }
 
-                let constant_index = compiler.parse_variable("Expect parameter name.");
-                compiler.define_variable(constant_index);
+        if !compiler.function.is_getter {
+            compiler.consume(TokenType::LeftParen, "Expect '(' after function name.");
+            if !compiler.check(TokenType::RightParen) {
+                loop {
+                    if compiler.function.arity == 255 {
+                        self.error_at_current("Can't have more than 255 parameters.");
+                    }
+                    compiler.function.arity += 1;
 
-                if !compiler.match_token(TokenType::Comma) {
-                    break;
+                    let constant_index = compiler.parse_variable("Expect parameter name.");
+                    compiler.define_variable(constant_index);
+
+                    if !compiler.match_token(TokenType::Comma) {
+                        break;
+                    }
                 }
             }
-        }
 
-        compiler.consume(TokenType::RightParen, "Expect ')' after parameters.");
+            compiler.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        }
         compiler.consume(TokenType::LeftBrace, "Expect '{' before function body.");
         compiler.block();
 
         // We have to capture this information about local capture like this because
         // copies of `self` are created to pass in as the enclosing compiler and so
         // the actual locals in `self` don't ever get modified
-        match &compiler.enclosing {
+        match compiler.enclosing.take() {
             Some(enclosing) => {
                 let enclosing_locals = enclosing.locals;
                 for i in 0..self.local_count as usize {
                     self.locals[i].is_captured = enclosing_locals[i].is_captured;
+                    self.locals[i].is_read = enclosing_locals[i].is_read;
                 }
+
+                // Closures nested deeper than this one mark variables in
+                // the copy of our own enclosing compiler, so keep that copy
+                self.enclosing = enclosing.enclosing;
             }
             _ => {}
         }
 
+        // Functions don't end their outermost scope, so their body's locals
+        // are checked here. Parameters are left alone since callbacks often
+        // have to accept arguments they don't need.
+        for idx in (compiler.function.arity as usize + 1)..compiler.local_count as usize {
+            compiler.warn_unused_local(compiler.locals[idx]);
+        }
+
         let func = compiler.end_compiler().to_owned();
 
         // disassemble_chunk(&func.chunk, format!("{:?}", &func.name).as_str());
@@ -1258,6 +2102,13 @@ impl Compiler {
         // state to the outside compiler
         self.patch_parser(compiler.parser.previous, compiler.parser.current);
         self.scanner = compiler.scanner.to_owned();
+
+        // Errors inside the function body have to be reported by whoever
+        // kicked off the compile, which is always the outermost compiler
+        self.parser.had_error |= compiler.parser.had_error;
+        self.parser.panic_mode = compiler.parser.panic_mode;
+        self.parser.errors.append(&mut compiler.parser.errors);
+        self.parser.warnings.append(&mut compiler.parser.warnings);
     }
 
     fn fun_declaration(&mut self) {
@@ -1270,32 +2121,83 @@ impl Compiler {
 
     fn class_declaration(&mut self) {
         self.consume(TokenType::Identifier, "Expect class name.");
+        let class_name = self.parser.previous;
 
         let lexeme = self.scanner.source[self.parser.previous.start
             ..(self.parser.previous.start + self.parser.previous.length)]
             .to_owned();
-        let index_of_class_name = self.current_chunk().write_class(Class { name: lexeme });
+        let index_of_class_name = self.current_chunk().write_class(Class::new(lexeme));
 
         self.declare_variable();
+        self.mark_initialized();
 
         self.emit_bytes(OpCode::Class as u8, index_of_class_name as u8);
-        self.define_variable(index_of_class_name as u8);
+
+        let has_superclass = self.match_token(TokenType::Less);
+        if has_superclass {
+            self.consume(TokenType::Identifier, "Expect superclass name.");
+            if self.identifiers_equal(class_name, self.parser.previous) {
+                self.error("A class can't inherit from itself.");
+            }
+
+            self.variable(false);
+            self.emit_byte(OpCode::Inherit as u8);
+        }
+
+        self.current_class = Some(ClassCompiler {
+            enclosing: self.current_class.take().map(Box::new),
+            has_superclass,
+        });
 
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.method();
+        }
         self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+
+        self.current_class = self
+            .current_class
+            .take()
+            .and_then(|class| class.enclosing)
+            .map(|enclosing| *enclosing);
+
+        // Classes are shared and can't change once anything else holds one,
+        // so every method is attached to the class while it's still on top
+        // of the stack and only then is it defined
+        self.define_variable(index_of_class_name as u8);
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenType::Identifier, "Expect method name.");
+        let lexeme = self.scanner.source[self.parser.previous.start
+            ..(self.parser.previous.start + self.parser.previous.length)]
+            .to_owned();
+
+        let function_type = if lexeme == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        let index_of_name = self.current_chunk().write_string(lexeme);
+
+        self.function(function_type);
+        self.emit_bytes(OpCode::Method as u8, index_of_name as u8);
     }
 
     fn synchronize(&mut self) {
         self.parser.panic_mode = false;
 
-        let synchronized_tokens: [u8; 8] = [
+        let synchronized_tokens: [u8; 11] = [
             TokenType::Class as u8,
             TokenType::Fun as u8,
             TokenType::Var as u8,
+            TokenType::Const as u8,
             TokenType::For as u8,
             TokenType::If as u8,
             TokenType::While as u8,
+            TokenType::Do as u8,
             TokenType::Print as u8,
+            TokenType::Println as u8,
             TokenType::Return as u8,
         ];
 
@@ -1313,17 +2215,26 @@ impl Compiler {
         }
     }
 
+    // `print` writes the value as is, `println` follows it with a newline
+    fn print_statement(&mut self, op: OpCode) {
+        self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after value.");
+        self.emit_byte(op as u8);
+    }
+
     fn statement(&mut self) {
         if self.match_token(TokenType::Print) {
-            self.expression();
-            self.consume(TokenType::Semicolon, "Expect ';' after value.");
-            self.emit_byte(OpCode::Print as u8);
+            self.print_statement(OpCode::Print);
+        } else if self.match_token(TokenType::Println) {
+            self.print_statement(OpCode::Println);
         } else if self.match_token(TokenType::If) {
             self.if_statement();
         } else if self.match_token(TokenType::Return) {
             self.return_statement();
         } else if self.match_token(TokenType::While) {
             self.while_statement();
+        } else if self.match_token(TokenType::Do) {
+            self.do_while_statement();
         } else if self.match_token(TokenType::For) {
             self.for_statement();
         } else if self.match_token(TokenType::LeftBrace) {
@@ -1338,6 +2249,8 @@ impl Compiler {
     fn declaration(&mut self) {
         if self.match_token(TokenType::Var) {
             self.var_declaration();
+        } else if self.match_token(TokenType::Const) {
+            self.const_declaration();
         } else if self.match_token(TokenType::Fun) {
             self.fun_declaration();
         } else if self.match_token(TokenType::Class) {
@@ -1370,6 +2283,19 @@ impl Compiler {
 
         return if had_error { None } else { Some(function) };
     }
+
+    // Compiles a whole script in one go, handing back either the function
+    // or every error that was found. Warnings are dropped, so callers that
+    // want them should drive a `Compiler` themselves.
+    pub fn compile_source(source: &str) -> Result<Function, Vec<CompileError>> {
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+        match compiler.compile(None) {
+            Some(function) => return Ok(function.to_owned()),
+            None => return Err(compiler.errors().clone()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1378,34 +2304,389 @@ mod tests {
 
     use super::*;
 
+    fn compile_chunk(source: &str) -> Chunk {
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+        assert!(compiler.compile(None).is_some());
+
+        compiler.current_chunk().clone()
+    }
+
     #[test]
     fn basic_arithmetic_opcodes() {
-        let source = String::from("1 + 2;");
-        let scanner = Scanner::new(source);
+        let chunk = compile_chunk("var a; a + 2;");
+
+        assert_eq!(
+            chunk.code[3..],
+            [
+                OpCode::GetGlobal as u8,
+                1,
+                OpCode::Constant as u8,
+                2,
+                OpCode::Add as u8,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+        assert!(matches!(chunk.constants[2], Value::Int(2)));
+    }
+
+    #[test]
+    fn constant_folding() {
+        let chunk = compile_chunk("1 + 2;");
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::Constant as u8,
+                0,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+        assert_eq!(chunk.constants.len(), 1);
+        assert!(matches!(chunk.constants[0], Value::Int(3)));
+
+        let chunk = compile_chunk("1.0 + 2;");
+        assert!(matches!(chunk.constants[..], [Value::Number(n)] if n == 3.0));
+
+        // Nested expressions fold all the way down
+        let chunk = compile_chunk("(2 + 3) * 4 - 10 / 5;");
+        assert!(matches!(chunk.constants[..], [Value::Int(18)]));
+    }
+
+    #[test]
+    fn constant_folding_leaves_the_rest_alone() {
+        // Only the `2 * 3` is constant, `a + 2` has to happen at runtime
+        let chunk = compile_chunk("var a = 1; a + 2 * 3;");
+        assert!(chunk.code.contains(&(OpCode::Add as u8)));
+        assert!(!chunk.code.contains(&(OpCode::Multiply as u8)));
+
+        // Errors are still raised when the program runs
+        for source in ["1 / 0;", "1.5 / 0;", "9223372036854775807 + 1;"] {
+            let chunk = compile_chunk(source);
+            assert_eq!(chunk.constants.len(), 2, "{}", source);
+        }
+
+        let chunk = compile_chunk("\"a\" + 1;");
+        assert!(chunk.code.contains(&(OpCode::Add as u8)));
+    }
+
+    fn compile_errors(source: &str) -> Vec<String> {
+        let scanner = Scanner::new(String::from(source));
         let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
 
-        let compile_result = compiler.compile(None);
+        assert!(compiler.compile(None).is_none());
 
-        assert!(compile_result.is_some());
+        compiler
+            .errors()
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
 
-        let two = compiler.current_chunk().constants.pop();
-        let one = compiler.current_chunk().constants.pop();
+    #[test]
+    fn unexpected_character() {
+        assert_eq!(
+            compile_errors("var a = 1 # 2;"),
+            vec!["[line 1, col 10] Error: Unexpected character '#'."]
+        );
+    }
 
-        match two {
-            Some(Value::Number(n)) => {
-                if n != 2.0 {
-                    panic!("Expected 2.0, got {}", n)
-                }
-            }
-            _ => panic!("Expected number, got {:?}", two),
+    #[test]
+    fn unterminated_string() {
+        assert_eq!(
+            compile_errors("print \"abc;"),
+            vec!["[line 1, col 6] Error: Unterminated string literal."]
+        );
+    }
+
+    #[test]
+    fn chained_property_assignment() {
+        let chunk = compile_chunk("var a; a.b.c = 1;");
+        let name = |idx: u8| match &chunk.constants[idx as usize] {
+            Value::String(s) => s.to_string(),
+            value => panic!("Expected a property name, got {:?}", value),
+        };
+
+        assert_eq!(chunk.code[5], OpCode::GetProperty as u8);
+        assert_eq!(name(chunk.code[6]), "b");
+        assert_eq!(chunk.code[7], OpCode::Constant as u8);
+        assert_eq!(chunk.code[9], OpCode::SetProperty as u8);
+        assert_eq!(name(chunk.code[10]), "c");
+    }
+
+    #[test]
+    fn compound_assignment_needs_a_property() {
+        assert_eq!(
+            compile_errors("var a; a + a.b += 1;"),
+            vec!["[line 1, col 15] Error at +=: Invalid assignment target."]
+        );
+    }
+
+    #[test]
+    fn compile_source() {
+        match Compiler::compile_source("var a = 1; println a;") {
+            Ok(function) => assert!(function.name.is_none()),
+            Err(errors) => panic!("Expected it to compile, got {:?}", errors),
         }
-        match one {
-            Some(Value::Number(n)) => {
-                if n != 1.0 {
-                    panic!("Expected 1.0, got {}", n)
-                }
+
+        match Compiler::compile_source("var a = ;\nprint 1 +;") {
+            Ok(_) => panic!("Expected compile errors"),
+            Err(errors) => {
+                let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                assert_eq!(
+                    errors,
+                    vec![
+                        "[line 1, col 8] Error at ;: Expect expression.",
+                        "[line 2, col 9] Error at ;: Expect expression.",
+                    ]
+                );
             }
-            _ => panic!("Expected number, got {:?}", two),
         }
     }
+
+    #[test]
+    fn trailing_comma_in_var_declaration() {
+        assert_eq!(
+            compile_errors("var a,;"),
+            vec!["[line 1, col 6] Error at ;: Expect variable name."]
+        );
+    }
+
+    #[test]
+    fn parse_errors_are_collected() {
+        let scanner = Scanner::new(String::from("fun 123() {}"));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+        assert!(compiler.compile(None).is_none());
+
+        let error = &compiler.errors()[0];
+        assert_eq!(error.message, "Expect function name.");
+        assert_eq!(error.line, 1);
+        assert_eq!(error.column, 4);
+        assert_eq!(error.start, 4);
+        assert_eq!(error.length, 3);
+        assert_eq!(
+            error.to_string(),
+            "[line 1, col 4] Error at 123: Expect function name."
+        );
+    }
+
+    #[test]
+    fn this_outside_of_class() {
+        let errors = compile_errors("print this;");
+        assert_eq!(
+            errors,
+            vec!["[line 1, col 6] Error at this: Can't use 'this' outside of a class."]
+        );
+
+        let errors = compile_errors("fun f() {\n  return this;\n}");
+        assert_eq!(
+            errors,
+            vec!["[line 2, col 9] Error at this: Can't use 'this' outside of a class."]
+        );
+    }
+
+    #[test]
+    fn super_outside_of_class() {
+        let errors = compile_errors("super.method();");
+        assert_eq!(
+            errors,
+            vec!["[line 1, col 0] Error at super: Can't use 'super' outside of a class with a superclass."]
+        );
+
+        let errors = compile_errors("fun f() { super.method(); }");
+        assert_eq!(
+            errors,
+            vec!["[line 1, col 10] Error at super: Can't use 'super' outside of a class with a superclass."]
+        );
+
+        let errors = compile_errors("class A { f() { return super.f(); } }");
+        assert_eq!(
+            errors,
+            vec!["[line 1, col 23] Error at super: Can't use 'super' outside of a class with a superclass."]
+        );
+
+        // compile_warnings only returns once the program has compiled
+        assert!(
+            compile_warnings("class A {} class B < A { f() { return super.f(); } }").is_empty()
+        );
+    }
+
+    #[test]
+    fn return_from_top_level() {
+        let errors = compile_errors("return 1;");
+        assert_eq!(
+            errors,
+            vec!["[line 1, col 0] Error at return: Can't return from top-level code."]
+        );
+    }
+
+    #[test]
+    fn return_value_from_initializer() {
+        let errors = compile_errors("class A { init() { return 1; } }");
+        assert_eq!(
+            errors,
+            vec!["[line 1, col 19] Error at return: Can't return a value from an initializer."]
+        );
+
+        let scanner = Scanner::new(String::from("class A { init() { return; } }"));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        assert!(compiler.compile(None).is_some());
+    }
+
+    #[test]
+    fn error_with_source() {
+        let source = "var a = 1;\nfun 123() {}";
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+        assert!(compiler.compile(None).is_none());
+
+        assert_eq!(
+            compiler.errors()[0].with_source(source),
+            "[line 2, col 4] Error at 123: Expect function name.\n    fun 123() {}\n        ^^^"
+        );
+    }
+
+    #[test]
+    fn nested_function_constants_stay_in_their_own_chunk() {
+        let scanner = Scanner::new(String::from(
+            "var outer = 1; fun f() { var inner = \"inner\"; return 42; }",
+        ));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        let script = compiler.compile(None).unwrap().to_owned();
+
+        let is_inner_constant = |value: &Value| match value {
+            Value::String(s) => &**s == "inner",
+            Value::Int(n) => *n == 42,
+            _ => false,
+        };
+
+        // The script only holds its own globals, its own number, and the
+        // nested function as a single entry
+        assert!(!script.chunk.constants.iter().any(is_inner_constant));
+        assert_eq!(script.chunk.constants.len(), 4);
+
+        let function = script
+            .chunk
+            .constants
+            .iter()
+            .find_map(|value| match value {
+                Value::Function(function) => Some(function),
+                _ => None,
+            })
+            .expect("Expected the nested function in the script's constants");
+
+        assert_eq!(
+            function
+                .chunk
+                .constants
+                .iter()
+                .filter(|value| is_inner_constant(value))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn too_many_arguments() {
+        let args = vec!["nil"; 256].join(", ");
+        let errors = compile_errors(format!("fun f() {{}} f({});", args).as_str());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Can't have more than 255 arguments."));
+
+        let args = vec!["nil"; 255].join(", ");
+        let scanner = Scanner::new(format!("fun f() {{}} f({});", args));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        assert!(compiler.compile(None).is_some());
+    }
+
+    #[test]
+    fn trailing_comma_in_arguments() {
+        let errors = compile_errors("fun f(a, b) {} f(1, 2,);");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Expect expression after ',' in arguments."));
+    }
+
+    #[test]
+    fn assign_to_constant() {
+        let errors = compile_errors("{ const x = 1; x = 2; }");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Cannot assign to constant 'x'."));
+
+        let errors = compile_errors("const PI = 3.14; PI = 3;");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Cannot assign to constant 'PI'."));
+
+        let errors = compile_errors("const PI = 3.14; fun f() { PI = 3; }");
+        assert!(errors[0].contains("Cannot assign to constant 'PI'."));
+
+        let errors = compile_errors("fun f() { const x = 1; fun g() { x = 2; } }");
+        assert!(errors[0].contains("Cannot assign to constant 'x'."));
+
+        let errors = compile_errors("const x;");
+        assert!(errors[0].contains("Expect '=' after constant name."));
+    }
+
+    #[test]
+    fn constants_can_be_read() {
+        let scanner = Scanner::new(String::from(
+            "const a = 1; { const b = a + 1; var c = b; c = 3; }",
+        ));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        assert!(compiler.compile(None).is_some());
+    }
+
+    fn compile_warnings(source: &str) -> Vec<String> {
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+        assert!(compiler.compile(None).is_some());
+
+        compiler
+            .warnings()
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn unused_locals_are_warned_about() {
+        assert_eq!(
+            compile_warnings("{\n  var unused = 1;\n}"),
+            vec!["[line 2] Warning: unused variable 'unused'"]
+        );
+        assert_eq!(
+            compile_warnings("fun f(a) {\n  var b = 1;\n}"),
+            vec!["[line 2] Warning: unused variable 'b'"]
+        );
+    }
+
+    #[test]
+    fn used_locals_are_not_warned_about() {
+        assert!(compile_warnings("{ var used = 1; print used; }").is_empty());
+        assert!(compile_warnings("var global = 1;").is_empty());
+        assert!(compile_warnings("fun f(unused_param) { var x = 1; return x; }").is_empty());
+        assert!(compile_warnings(
+            "fun outer() {
+              var x = 1;
+              fun middle() {
+                fun inner() { return x; }
+                return inner;
+              }
+              return middle;
+            }"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn class_inheriting_from_itself() {
+        let errors = compile_errors("class A < A {}");
+        assert!(errors[0].contains("A class can't inherit from itself."));
+    }
 }
@@ -1,13 +1,14 @@
 use crate::{
     chunk::{Chunk, OpCode},
-    value::Value,
+    value::{Function, Value},
 };
 
-fn get_value_debug_string(value: &Value) -> String {
+pub fn get_value_debug_string(value: &Value) -> String {
     match value {
         Value::Nil => "nil".to_string(),
         Value::Boolean(v) => format!("{}", v),
-        Value::Number(v) => format!("{}", v),
+        Value::Int(v) => format!("{}", v),
+        Value::Number(v) => format!("{:?}", v),
         Value::String(v) => format!("'{}'", v),
         Value::Function(v) => match &v.name {
             Some(name) => {
@@ -29,148 +30,272 @@ fn get_value_debug_string(value: &Value) -> String {
         Value::Upvalue(up) => format!("<upvalue {:?}>", up),
         Value::Class(c) => format!("{}", c.name),
         Value::Instance(i) => format!("{}", i.borrow().class.name),
+        Value::BoundMethod(b) => match &b.method.function.name {
+            Some(name) => format!("<bound method {}>", name),
+            None => String::from("<bound method>"),
+        },
+        Value::List(list) => format!(
+            "[{}]",
+            list.borrow()
+                .iter()
+                .map(get_value_debug_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Value::Map(map) => {
+            let map = map.borrow();
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+
+            format!(
+                "{{{}}}",
+                keys.into_iter()
+                    .map(|key| format!("'{}': {}", key, get_value_debug_string(&map[key])))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
     }
 }
 
+// `OP_LOOP` jumps backwards, so the target is measured back from the end of
+// the instruction, i.e. past the opcode and its two offset bytes
+fn loop_target(chunk: &Chunk, offset: usize) -> usize {
+    let jump = (chunk.code[offset + 1] as u16) << 8 | chunk.code[offset + 2] as u16;
+    offset + 3 - jump as usize
+}
+
 pub mod print_debug {
+    use std::fmt::Write;
+
     use super::*;
 
-    fn simple_instruction(name: &str, offset: usize) -> usize {
-        println!("{}", name);
+    fn simple_instruction(output: &mut String, name: &str, offset: usize) -> usize {
+        writeln!(output, "{}", name).unwrap();
         return offset + 1;
     }
 
-    fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-        print!("CHUNK OFFSET - {:0>4} | ", offset);
-        if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
-            print!("LINE -    | ");
+    // `lines` holds the line of every byte, see `Chunk::byte_lines`
+    fn disassemble_instruction(
+        output: &mut String,
+        chunk: &Chunk,
+        lines: &[usize],
+        offset: usize,
+    ) -> usize {
+        write!(output, "CHUNK OFFSET - {:0>4} | ", offset).unwrap();
+        if offset > 0 && lines[offset] == lines[offset - 1] {
+            write!(output, "LINE -    | ").unwrap();
         } else {
-            print!("LINE - {:0>4} ", chunk.lines[offset]);
+            write!(output, "LINE - {:0>4} | ", lines[offset]).unwrap();
         }
 
         let instruction = OpCode::from_u8(chunk.code[offset]).unwrap();
 
         match instruction {
             OpCode::Return => {
-                println!("OP_RETURN");
+                writeln!(output, "OP_RETURN").unwrap();
                 return offset + 1;
             }
             OpCode::Constant => {
                 let constant = &chunk.constants[chunk.code[offset + 1] as usize];
-                println!("{}: {}", OpCode::Constant, get_value_debug_string(constant));
+                writeln!(
+                    output,
+                    "{}: {}",
+                    OpCode::Constant,
+                    get_value_debug_string(constant)
+                )
+                .unwrap();
 
                 return offset + 2;
             }
             OpCode::Add => {
-                return simple_instruction("OP_ADD", offset);
+                return simple_instruction(output, "OP_ADD", offset);
             }
             OpCode::Subtract => {
-                return simple_instruction("OP_SUBTRACT", offset);
+                return simple_instruction(output, "OP_SUBTRACT", offset);
             }
             OpCode::Multiply => {
-                return simple_instruction("OP_MULTIPLY", offset);
+                return simple_instruction(output, "OP_MULTIPLY", offset);
             }
             OpCode::Divide => {
-                return simple_instruction("OP_DIVIDE", offset);
+                return simple_instruction(output, "OP_DIVIDE", offset);
             }
             OpCode::True => {
-                return simple_instruction("OP_TRUE", offset);
+                return simple_instruction(output, "OP_TRUE", offset);
             }
             OpCode::False => {
-                return simple_instruction("OP_FALSE", offset);
+                return simple_instruction(output, "OP_FALSE", offset);
             }
             OpCode::Nil => {
-                return simple_instruction("OP_NIL", offset);
+                return simple_instruction(output, "OP_NIL", offset);
             }
             OpCode::Equal => {
-                return simple_instruction("OP_EQUAL", offset);
+                return simple_instruction(output, "OP_EQUAL", offset);
             }
             OpCode::Greater => {
-                return simple_instruction("OP_GREATER", offset);
+                return simple_instruction(output, "OP_GREATER", offset);
             }
             OpCode::Less => {
-                return simple_instruction("OP_LESS", offset);
+                return simple_instruction(output, "OP_LESS", offset);
             }
             OpCode::Negate => {
-                return simple_instruction("OP_NEGATE", offset);
+                return simple_instruction(output, "OP_NEGATE", offset);
             }
             OpCode::Not => {
-                return simple_instruction("OP_NOT", offset);
+                return simple_instruction(output, "OP_NOT", offset);
+            }
+            OpCode::BitAnd => {
+                return simple_instruction(output, "OP_BIT_AND", offset);
+            }
+            OpCode::BitOr => {
+                return simple_instruction(output, "OP_BIT_OR", offset);
+            }
+            OpCode::BitXor => {
+                return simple_instruction(output, "OP_BIT_XOR", offset);
+            }
+            OpCode::ShiftLeft => {
+                return simple_instruction(output, "OP_SHIFT_LEFT", offset);
+            }
+            OpCode::ShiftRight => {
+                return simple_instruction(output, "OP_SHIFT_RIGHT", offset);
+            }
+            OpCode::Dup => {
+                return simple_instruction(output, "OP_DUP", offset);
+            }
+            OpCode::Rotate => {
+                return simple_instruction(output, "OP_ROTATE", offset);
+            }
+            OpCode::Index => {
+                return simple_instruction(output, "OP_INDEX", offset);
+            }
+            OpCode::SetIndex => {
+                return simple_instruction(output, "OP_SET_INDEX", offset);
             }
             OpCode::Pop => {
-                return simple_instruction("OP_POP", offset);
+                return simple_instruction(output, "OP_POP", offset);
             }
             OpCode::Print => {
-                return simple_instruction("OP_PRINT", offset);
+                return simple_instruction(output, "OP_PRINT", offset);
+            }
+            OpCode::Println => {
+                return simple_instruction(output, "OP_PRINTLN", offset);
+            }
+            OpCode::Inherit => {
+                return simple_instruction(output, "OP_INHERIT", offset);
+            }
+            OpCode::IsInstance => {
+                return simple_instruction(output, "OP_IS_INSTANCE", offset);
+            }
+            OpCode::NotEqual => {
+                return simple_instruction(output, "OP_NOT_EQUAL", offset);
+            }
+            OpCode::GreaterEqual => {
+                return simple_instruction(output, "OP_GREATER_EQUAL", offset);
+            }
+            OpCode::LessEqual => {
+                return simple_instruction(output, "OP_LESS_EQUAL", offset);
+            }
+            OpCode::Power => {
+                return simple_instruction(output, "OP_POWER", offset);
+            }
+            OpCode::Length => {
+                return simple_instruction(output, "OP_LENGTH", offset);
             }
             OpCode::DefineGlobal => {
                 let constant = &chunk.constants[chunk.code[offset + 1] as usize];
-                println!(
+                writeln!(
+                    output,
                     "{}: {}",
                     OpCode::DefineGlobal,
                     get_value_debug_string(constant)
-                );
+                )
+                .unwrap();
 
                 return offset + 2;
             }
             OpCode::GetGlobal => {
                 let constant = &chunk.constants[chunk.code[offset + 1] as usize];
-                println!(
+                writeln!(
+                    output,
                     "{}: {}",
                     OpCode::GetGlobal,
                     get_value_debug_string(constant)
-                );
+                )
+                .unwrap();
 
                 return offset + 2;
             }
             OpCode::SetGlobal => {
                 let constant = &chunk.constants[chunk.code[offset + 1] as usize];
-                println!(
+                writeln!(
+                    output,
                     "{}: {}",
                     OpCode::SetGlobal,
                     get_value_debug_string(constant)
-                );
+                )
+                .unwrap();
 
                 return offset + 2;
             }
             OpCode::GetLocal => {
                 let slot = chunk.code[offset + 1];
-                println!("{}: {}", OpCode::GetLocal, slot);
+                writeln!(output, "{}: {}", OpCode::GetLocal, slot).unwrap();
                 return offset + 2;
             }
             OpCode::SetLocal => {
                 let slot = chunk.code[offset + 1];
-                println!("{}: {}", OpCode::SetLocal, slot);
+                writeln!(output, "{}: {}", OpCode::SetLocal, slot).unwrap();
                 return offset + 2;
             }
             OpCode::JumpIfFalse => {
                 let jump = (chunk.code[offset + 1] as u16) << 8 | chunk.code[offset + 2] as u16;
-                println!(
+                writeln!(
+                    output,
                     "{} {} -> {}",
                     OpCode::JumpIfFalse,
                     offset,
                     offset + 3 + jump as usize
-                );
+                )
+                .unwrap();
 
                 return offset + 3;
             }
             OpCode::Jump => {
                 let jump = (chunk.code[offset + 1] as u16) << 8 | chunk.code[offset + 2] as u16;
-                println!(
+                writeln!(
+                    output,
                     "{} {} -> {}",
                     OpCode::Jump,
                     offset,
                     offset + 3 + jump as usize
-                );
+                )
+                .unwrap();
                 return offset + 3;
             }
             OpCode::Loop => {
-                println!("op code loop");
+                writeln!(
+                    output,
+                    "{} {} -> {}",
+                    OpCode::Loop,
+                    offset,
+                    loop_target(chunk, offset)
+                )
+                .unwrap();
                 return offset + 3;
             }
             OpCode::Call => {
                 let slot = chunk.code[offset + 1];
-                println!("OP_CALL {}", slot);
+                writeln!(output, "OP_CALL {}", slot).unwrap();
+                return offset + 2;
+            }
+            OpCode::BuildList => {
+                let item_count = chunk.code[offset + 1];
+                writeln!(output, "OP_BUILD_LIST {}", item_count).unwrap();
+                return offset + 2;
+            }
+            OpCode::BuildMap => {
+                let entry_count = chunk.code[offset + 1];
+                writeln!(output, "OP_BUILD_MAP {}", entry_count).unwrap();
                 return offset + 2;
             }
             OpCode::Closure => {
@@ -180,7 +305,7 @@ pub mod print_debug {
 
                 match value {
                     Value::Function(function) => {
-                        println!("OP_CLOSURE {:?}", function.name);
+                        writeln!(output, "OP_CLOSURE {:?}", function.name).unwrap();
 
                         for idx in 0..(function.upvalue_count as usize) {
                             // at idx = 0, the index for the array access here is offset + 1 + 0 + 1
@@ -191,7 +316,7 @@ pub mod print_debug {
                             let is_local = chunk.code[(offset + 1) + (2 * idx + 1)];
                             let index = chunk.code[(offset + 1) + (2 * idx + 2)];
 
-                            println!("is local: {}\nindex: {}", is_local, index);
+                            writeln!(output, "is local: {}\nindex: {}", is_local, index).unwrap();
                         }
                         offset_inc_value += 2 * function.upvalue_count;
                     }
@@ -202,40 +327,121 @@ pub mod print_debug {
             }
             OpCode::GetUpvalue => {
                 let slot = chunk.code[offset + 1];
-                println!("{}: {}", OpCode::GetUpvalue, slot);
+                writeln!(output, "{}: {}", OpCode::GetUpvalue, slot).unwrap();
 
                 return offset + 2;
             }
             OpCode::SetUpvalue => {
                 let slot = chunk.code[offset + 1];
-                println!("{}: {}", OpCode::SetUpvalue, slot);
+                writeln!(output, "{}: {}", OpCode::SetUpvalue, slot).unwrap();
 
                 return offset + 2;
             }
             OpCode::CloseUpvalue => {
-                return simple_instruction(format!("{}", OpCode::CloseUpvalue).as_str(), offset)
+                return simple_instruction(
+                    output,
+                    format!("{}", OpCode::CloseUpvalue).as_str(),
+                    offset,
+                )
             }
             OpCode::Class => {
-                todo!("class in disassemble_instruction");
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+                writeln!(
+                    output,
+                    "{}: {}",
+                    OpCode::Class,
+                    get_value_debug_string(constant)
+                )
+                .unwrap();
+
+                return offset + 2;
             }
             OpCode::GetProperty => {
-                todo!("get property");
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+                writeln!(
+                    output,
+                    "{}: {}",
+                    OpCode::GetProperty,
+                    get_value_debug_string(constant)
+                )
+                .unwrap();
+
+                return offset + 2;
+            }
+            OpCode::GetSuper => {
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+                writeln!(
+                    output,
+                    "{}: {}",
+                    OpCode::GetSuper,
+                    get_value_debug_string(constant)
+                )
+                .unwrap();
+
+                return offset + 2;
             }
             OpCode::SetProperty => {
-                todo!("set property");
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+                writeln!(
+                    output,
+                    "{}: {}",
+                    OpCode::SetProperty,
+                    get_value_debug_string(constant)
+                )
+                .unwrap();
+
+                return offset + 2;
+            }
+            OpCode::Method => {
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+                writeln!(
+                    output,
+                    "{}: {}",
+                    OpCode::Method,
+                    get_value_debug_string(constant)
+                )
+                .unwrap();
+
+                return offset + 2;
             }
         }
     }
 
     pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-        println!("==== {} ====\n\n", name);
+        print!("{}", disassemble_chunk_to_string(chunk, name));
+    }
 
+    // Same as `disassemble_chunk`, for callers that want to keep the text
+    pub fn disassemble_chunk_to_string(chunk: &Chunk, name: &str) -> String {
+        let mut output = String::new();
+        writeln!(output, "==== {} ====\n\n", name).unwrap();
+
+        let lines: Vec<usize> = chunk.byte_lines().collect();
         let mut offset = 0;
         while offset < chunk.code.len() {
-            offset = disassemble_instruction(chunk, offset);
+            offset = disassemble_instruction(&mut output, chunk, &lines, offset);
+        }
+
+        writeln!(output, "\n\n==== END CHUNK DISASSEMBLY ====\n\n").unwrap();
+        return output;
+    }
+
+    // The function's chunk followed by the chunks of every function
+    // declared inside it, all the way down
+    pub fn disassemble_function_to_string(function: &Function) -> String {
+        let name = match &function.name {
+            Some(name) => format!("<fn {}>", name),
+            None => String::from("<script>"),
+        };
+        let mut output = disassemble_chunk_to_string(&function.chunk, name.as_str());
+
+        for constant in &function.chunk.constants {
+            if let Value::Function(nested) = constant {
+                output.push_str(disassemble_function_to_string(nested).as_str());
+            }
         }
 
-        println!("\n\n==== END CHUNK DISASSEMBLY ====\n\n");
+        return output;
     }
 }
 
@@ -248,7 +454,7 @@ pub mod write_debug {
         return (format!("{}\n", name), offset + 1);
     }
 
-    fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
         let instruction = OpCode::from_u8(chunk.code[offset]).unwrap();
 
         match instruction {
@@ -302,12 +508,63 @@ pub mod write_debug {
             OpCode::Not => {
                 return simple_instruction("OP_NOT", offset);
             }
+            OpCode::BitAnd => {
+                return simple_instruction("OP_BIT_AND", offset);
+            }
+            OpCode::BitOr => {
+                return simple_instruction("OP_BIT_OR", offset);
+            }
+            OpCode::BitXor => {
+                return simple_instruction("OP_BIT_XOR", offset);
+            }
+            OpCode::ShiftLeft => {
+                return simple_instruction("OP_SHIFT_LEFT", offset);
+            }
+            OpCode::ShiftRight => {
+                return simple_instruction("OP_SHIFT_RIGHT", offset);
+            }
+            OpCode::Dup => {
+                return simple_instruction("OP_DUP", offset);
+            }
+            OpCode::Rotate => {
+                return simple_instruction("OP_ROTATE", offset);
+            }
+            OpCode::Index => {
+                return simple_instruction("OP_INDEX", offset);
+            }
+            OpCode::SetIndex => {
+                return simple_instruction("OP_SET_INDEX", offset);
+            }
             OpCode::Pop => {
                 return simple_instruction("OP_POP", offset);
             }
             OpCode::Print => {
                 return simple_instruction("OP_PRINT", offset);
             }
+            OpCode::Println => {
+                return simple_instruction("OP_PRINTLN", offset);
+            }
+            OpCode::Inherit => {
+                return simple_instruction("OP_INHERIT", offset);
+            }
+            OpCode::IsInstance => {
+                return simple_instruction("OP_IS_INSTANCE", offset);
+            }
+            OpCode::NotEqual => {
+                return simple_instruction("OP_NOT_EQUAL", offset);
+            }
+            OpCode::GreaterEqual => {
+                return simple_instruction("OP_GREATER_EQUAL", offset);
+            }
+            OpCode::LessEqual => {
+                return simple_instruction("OP_LESS_EQUAL", offset);
+            }
+            OpCode::Power => {
+                return simple_instruction("OP_POWER", offset);
+            }
+            OpCode::Length => {
+                return simple_instruction("OP_LENGTH", offset);
+            }
             OpCode::DefineGlobal => {
                 let constant = &chunk.constants[chunk.code[offset + 1] as usize];
 
@@ -342,26 +599,12 @@ pub mod write_debug {
                 );
             }
             OpCode::GetLocal => {
-                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
-
-                return (
-                    format!(
-                        "OP_GET_LOCAL\nOP_CONSTANT\nCONSTANT: {}\n",
-                        get_value_debug_string(constant)
-                    ),
-                    offset + 2,
-                );
+                let slot = chunk.code[offset + 1];
+                return (format!("{}: {}\n", OpCode::GetLocal, slot), offset + 2);
             }
             OpCode::SetLocal => {
-                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
-
-                return (
-                    format!(
-                        "OP_SET_LOCAL\nOP_CONSTANT\nCONSTANT: {}\n",
-                        get_value_debug_string(constant)
-                    ),
-                    offset + 2,
-                );
+                let slot = chunk.code[offset + 1];
+                return (format!("{}: {}\n", OpCode::SetLocal, slot), offset + 2);
             }
             OpCode::JumpIfFalse => {
                 let jump = (chunk.code[offset + 1] as u16) << 8 | chunk.code[offset + 2] as u16;
@@ -387,32 +630,115 @@ pub mod write_debug {
                     offset + 3,
                 );
             }
-            OpCode::Loop => return ("opcode loop".to_owned(), offset + 3),
+            OpCode::Loop => {
+                return (
+                    format!(
+                        "{} {} -> {}\n",
+                        OpCode::Loop,
+                        offset,
+                        loop_target(chunk, offset)
+                    ),
+                    offset + 3,
+                );
+            }
             OpCode::Call => {
                 let slot = chunk.code[offset + 1];
-                return (format!("OP_CALL {}", slot), offset + 2);
+                return (format!("OP_CALL {}\n", slot), offset + 2);
+            }
+            OpCode::BuildList => {
+                let item_count = chunk.code[offset + 1];
+                return (format!("OP_BUILD_LIST {}\n", item_count), offset + 2);
+            }
+            OpCode::BuildMap => {
+                let entry_count = chunk.code[offset + 1];
+                return (format!("OP_BUILD_MAP {}\n", entry_count), offset + 2);
             }
             OpCode::Closure => {
                 let slot = chunk.code[offset + 1];
-                return (format!("OP_CLOSURE {}", slot), offset + 2);
+                let value = &chunk.constants[slot as usize];
+
+                match value {
+                    Value::Function(function) => {
+                        let mut output = format!("OP_CLOSURE {}\n", get_value_debug_string(value));
+
+                        // Each upvalue is encoded as an (is_local, index) byte pair
+                        // following the function constant
+                        for idx in 0..(function.upvalue_count as usize) {
+                            let is_local = chunk.code[(offset + 1) + (2 * idx + 1)];
+                            let index = chunk.code[(offset + 1) + (2 * idx + 2)];
+
+                            output.push_str(
+                                format!("is local: {}\nindex: {}\n", is_local, index).as_str(),
+                            );
+                        }
+
+                        return (output, offset + 2 + 2 * function.upvalue_count as usize);
+                    }
+                    v => panic!("Expect function at slot {} but received {:?}", slot, v),
+                }
             }
             OpCode::GetUpvalue => {
-                todo!("get upvalue");
+                let slot = chunk.code[offset + 1];
+                return (format!("{}: {}\n", OpCode::GetUpvalue, slot), offset + 2);
             }
             OpCode::SetUpvalue => {
-                todo!("set upvalue");
+                let slot = chunk.code[offset + 1];
+                return (format!("{}: {}\n", OpCode::SetUpvalue, slot), offset + 2);
             }
             OpCode::CloseUpvalue => {
-                todo!("close upvalue in debug");
+                return simple_instruction("OP_CLOSE_UPVALUE", offset);
             }
             OpCode::Class => {
-                todo!("class in debug to file");
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+
+                return (
+                    format!("OP_CLASS\nCONSTANT: {}\n", get_value_debug_string(constant)),
+                    offset + 2,
+                );
             }
             OpCode::GetProperty => {
-                todo!("get property");
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+
+                return (
+                    format!(
+                        "OP_GET_PROPERTY\nCONSTANT: {}\n",
+                        get_value_debug_string(constant)
+                    ),
+                    offset + 2,
+                );
+            }
+            OpCode::GetSuper => {
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+
+                return (
+                    format!(
+                        "OP_GET_SUPER\nCONSTANT: {}\n",
+                        get_value_debug_string(constant)
+                    ),
+                    offset + 2,
+                );
             }
             OpCode::SetProperty => {
-                todo!("set property");
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+
+                return (
+                    format!(
+                        "OP_SET_PROPERTY\nCONSTANT: {}\n",
+                        get_value_debug_string(constant)
+                    ),
+                    offset + 2,
+                );
+            }
+            OpCode::Method => {
+                let constant = &chunk.constants[chunk.code[offset + 1] as usize];
+
+                return (
+                    format!(
+                        "OP_METHOD\nCONSTANT: {}\n",
+                        get_value_debug_string(constant)
+                    ),
+                    offset + 2,
+                );
             }
         }
     }
@@ -425,12 +751,20 @@ pub mod write_debug {
         let mut debug_string: String;
         let mut current_line = 0;
         let source_lines: Vec<&str> = source.split('\n').collect();
+        let lines: Vec<usize> = chunk.byte_lines().collect();
 
         while offset < chunk.code.len() {
-            if chunk.lines[offset] != current_line {
-                current_line = chunk.lines[offset];
-                file.write_all(format!("\n\n{}\n\n", source_lines[current_line - 1]).as_bytes())
-                    .expect("Couldn't write to file");
+            if lines[offset] != current_line {
+                current_line = lines[offset];
+
+                // Lines start at 1, anything else has no source to show
+                if let Some(source_line) = current_line
+                    .checked_sub(1)
+                    .and_then(|idx| source_lines.get(idx))
+                {
+                    file.write_all(format!("\n\n{}\n\n", source_line).as_bytes())
+                        .expect("Couldn't write to file");
+                }
             }
 
             (debug_string, offset) = disassemble_instruction(chunk, offset);
@@ -440,3 +774,173 @@ pub mod write_debug {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{Compiler, FunctionType};
+    use crate::scanner::Scanner;
+
+    use super::*;
+
+    fn compile(source: &str) -> Chunk {
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+        assert!(compiler.compile(None).is_some());
+
+        compiler.current_chunk().clone()
+    }
+
+    fn instructions(chunk: &Chunk) -> Vec<String> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+
+        while offset < chunk.code.len() {
+            let (instruction, next_offset) = write_debug::disassemble_instruction(chunk, offset);
+            instructions.push(instruction);
+            offset = next_offset;
+        }
+
+        instructions
+    }
+
+    #[test]
+    fn for_loop_targets() {
+        let chunk = compile("for (var i = 0; i < 3; i = i + 1) {}");
+        let loops: Vec<String> = instructions(&chunk)
+            .into_iter()
+            .filter(|instruction| instruction.starts_with("OP_LOOP"))
+            .collect();
+
+        // The increment clause loops back to the condition, and the body
+        // loops back to the increment clause
+        assert_eq!(loops, vec!["OP_LOOP 22 -> 2\n", "OP_LOOP 25 -> 14\n"]);
+        assert_eq!(loop_target(&chunk, 22), 2);
+        assert_eq!(loop_target(&chunk, 25), 14);
+    }
+
+    #[test]
+    fn disassemble_to_string() {
+        let chunk = compile("var a = 1.5;\nprintln a + 2;");
+        let disassembly = print_debug::disassemble_chunk_to_string(&chunk, "arithmetic");
+
+        assert!(disassembly.starts_with("==== arithmetic ====\n"));
+        assert!(disassembly.contains("OP_ADD\n"));
+        assert!(disassembly.contains("OP_CONSTANT: 1.5\n"));
+        assert!(disassembly.contains("OP_CONSTANT: 2\n"));
+        assert!(disassembly.contains("CHUNK OFFSET - 0000 | LINE - 0001 | "));
+        assert!(disassembly.contains("==== END CHUNK DISASSEMBLY ===="));
+    }
+
+    #[test]
+    fn disassemble_nested_functions() {
+        let scanner = Scanner::new(String::from(
+            "fun outer() { fun inner() { return 1; } return inner; }",
+        ));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        let function = compiler.compile(None).unwrap().to_owned();
+
+        let disassembly = print_debug::disassemble_function_to_string(&function);
+        let script = disassembly.find("==== <script> ====").unwrap();
+        let outer = disassembly.find("==== <fn outer> ====").unwrap();
+        let inner = disassembly.find("==== <fn inner> ====").unwrap();
+
+        assert!(script < outer && outer < inner);
+    }
+
+    #[test]
+    fn collapsed_not_equal() {
+        let chunk = compile("var a = 1; var b = 2; a != b;");
+        let comparisons: Vec<String> = instructions(&chunk)
+            .into_iter()
+            .filter(|instruction| {
+                ["OP_EQUAL", "OP_NOT", "OP_NOT_EQUAL"].contains(&instruction.trim_end())
+            })
+            .collect();
+
+        assert_eq!(comparisons, vec!["OP_NOT_EQUAL\n"]);
+    }
+
+    #[test]
+    fn while_loop_target() {
+        let chunk = compile("var i = 0;\nwhile (i < 3) i = i + 1;");
+        let loops: Vec<String> = instructions(&chunk)
+            .into_iter()
+            .filter(|instruction| instruction.starts_with("OP_LOOP"))
+            .collect();
+
+        // Jumps back to re-evaluate the condition, right after the global
+        // definition on the first line
+        assert_eq!(loops, vec!["OP_LOOP 21 -> 4\n"]);
+        assert_eq!(chunk.line_at(4), 2);
+    }
+
+    #[test]
+    fn write_empty_program_to_file() {
+        for source in ["", "\n\n", "// nothing here"] {
+            let chunk = compile(source);
+            let path =
+                std::env::temp_dir().join(format!("rlox_debug_empty_{}.txt", std::process::id()));
+            write_debug::write_chunk_to_file(String::from(source), &chunk, path.to_str().unwrap());
+
+            let output = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert!(output.contains("OP_NIL\nOP_RETURN\n"));
+        }
+
+        // Lines that don't exist in the source are skipped over
+        let mut chunk = Chunk::new();
+        chunk.write_code(OpCode::Nil as u8, 0);
+        chunk.write_code(OpCode::Return as u8, 5);
+        let path =
+            std::env::temp_dir().join(format!("rlox_debug_no_lines_{}.txt", std::process::id()));
+        write_debug::write_chunk_to_file(String::new(), &chunk, path.to_str().unwrap());
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(output, "OP_NIL\nOP_RETURN\n");
+    }
+
+    #[test]
+    fn write_closures_and_classes_to_file() {
+        let source = "fun outer() {
+  var x = 1;
+  fun inner() {
+    x = x + 1;
+    return x;
+  }
+  return inner;
+}
+class Pair {}
+var pair = Pair();
+pair.first = outer();
+print pair.first;";
+
+        let chunk = compile(source);
+        let path = std::env::temp_dir().join(format!("rlox_debug_{}.txt", std::process::id()));
+        write_debug::write_chunk_to_file(String::from(source), &chunk, path.to_str().unwrap());
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(output.contains("OP_CLOSURE <fn outer>\n"));
+        assert!(output.contains("OP_CLASS\nCONSTANT: Pair\n"));
+        assert!(output.contains("OP_SET_PROPERTY\nCONSTANT: 'first'\n"));
+        assert!(output.contains("OP_GET_PROPERTY\nCONSTANT: 'first'\n"));
+
+        // The nested functions live in the constants of `outer`'s chunk
+        let outer = chunk
+            .constants
+            .iter()
+            .find_map(|constant| match constant {
+                Value::Function(f) if f.name.as_deref() == Some("outer") => Some(f.clone()),
+                _ => None,
+            })
+            .unwrap();
+        let instructions = instructions(&outer.chunk).concat();
+
+        assert!(instructions.contains("OP_CLOSURE <fn inner>\nis local: 1\nindex: 1\n"));
+    }
+}
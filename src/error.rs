@@ -0,0 +1,85 @@
+use std::{error::Error, fmt};
+
+use crate::{compiler::CompileError, vm::RuntimeError};
+
+// Everything that can go wrong while running a Lox program, for embedders
+// who want to pass errors around with `?` or box them up
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum LoxError {
+    Compile(Vec<CompileError>),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoxError::Compile(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            LoxError::Runtime(error) => {
+                write!(f, "{}", error)
+            }
+        }
+    }
+}
+
+impl Error for LoxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoxError::Compile(errors) => errors.first().map(|e| e as &(dyn Error + 'static)),
+            LoxError::Runtime(error) => Some(error),
+        }
+    }
+}
+
+impl From<Vec<CompileError>> for LoxError {
+    fn from(errors: Vec<CompileError>) -> Self {
+        LoxError::Compile(errors)
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        LoxError::Runtime(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{Compiler, FunctionType};
+    use crate::scanner::Scanner;
+
+    use super::*;
+
+    #[test]
+    fn compile_error_as_dyn_error() {
+        let scanner = Scanner::new(String::from("var = 3;"));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        assert!(compiler.compile(None).is_none());
+
+        let error: Box<dyn Error> = Box::new(LoxError::from(compiler.errors().clone()));
+
+        assert_eq!(
+            error.to_string(),
+            "[line 1, col 4] Error at =: Expect variable name."
+        );
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn runtime_error_as_dyn_error() {
+        let error: Box<dyn Error> = Box::new(LoxError::Runtime(RuntimeError {
+            message: String::from("Can't negate non-numeric value."),
+            line: 3,
+            span: None,
+            stack_trace: String::new(),
+        }));
+
+        assert_eq!(
+            error.to_string(),
+            "[line 3] Runtime error: Can't negate non-numeric value."
+        );
+    }
+}
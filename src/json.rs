@@ -0,0 +1,533 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::value::{Class, Instance, Value};
+
+// JSON objects don't have a class of their own, so they all become
+// instances of this one
+pub const OBJECT_CLASS_NAME: &str = "Object";
+
+// Arrays and objects are parsed recursively, so without a cap deeply nested
+// input would overflow the stack instead of being reported as an error
+const MAX_DEPTH: usize = 512;
+
+struct JsonParser {
+    source: Vec<char>,
+    current: usize,
+    // How many arrays and objects the parser is inside of
+    depth: usize,
+}
+
+impl JsonParser {
+    fn parse(&mut self) -> Result<Value, String> {
+        let value = self.value()?;
+
+        self.skip_whitespace();
+        if self.current < self.source.len() {
+            return Err(self.error("Unexpected trailing characters"));
+        }
+
+        return Ok(value);
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at position {}", message, self.current)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.current += 1;
+        }
+        return c;
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.current += 1;
+        }
+    }
+
+    fn consume(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.current += 1;
+                Ok(())
+            }
+            _ => Err(self.error(format!("Expected '{}'", expected).as_str())),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => self.nested(JsonParser::object),
+            Some('[') => self.nested(JsonParser::array),
+            Some('"') => Ok(Value::String(self.string()?.into())),
+            Some('t') => self.literal("true", Value::Boolean(true)),
+            Some('f') => self.literal("false", Value::Boolean(false)),
+            Some('n') => self.literal("null", Value::Nil),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Nesting too deep"));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        return value;
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.consume('{')?;
+        let mut fields = HashMap::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.current += 1;
+        } else {
+            loop {
+                self.skip_whitespace();
+                if self.peek() != Some('"') {
+                    return Err(self.error("Expected string key"));
+                }
+
+                let key = self.string()?;
+                self.consume(':')?;
+                let value = self.value()?;
+                fields.insert(key, value);
+
+                self.skip_whitespace();
+                match self.advance() {
+                    Some(',') => {}
+                    Some('}') => break,
+                    _ => return Err(self.error("Expected ',' or '}'")),
+                }
+            }
+        }
+
+        return Ok(Value::Instance(Rc::new(RefCell::new(Instance {
+            class: Rc::new(Class::new(String::from(OBJECT_CLASS_NAME))),
+            fields,
+        }))));
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.consume('[')?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.current += 1;
+        } else {
+            loop {
+                values.push(self.value()?);
+
+                self.skip_whitespace();
+                match self.advance() {
+                    Some(',') => {}
+                    Some(']') => break,
+                    _ => return Err(self.error("Expected ',' or ']'")),
+                }
+            }
+        }
+
+        return Ok(Value::List(Rc::new(RefCell::new(values))));
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.consume('"')?;
+        let mut s = String::new();
+
+        loop {
+            match self.advance() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.advance() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => s.push(self.unicode_escape()?),
+                    _ => return Err(self.error("Invalid escape sequence")),
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        if self.current + 4 > self.source.len() {
+            return Err(self.error("Invalid unicode escape"));
+        }
+
+        let hex: String = self.source[self.current..self.current + 4].iter().collect();
+        self.current += 4;
+
+        u32::from_str_radix(&hex, 16).map_err(|_| self.error("Invalid unicode escape"))
+    }
+
+    // Characters past U+FFFF are written as a pair of surrogate escapes,
+    // which only mean something together
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let mut code = self.hex_digits()?;
+
+        if (0xD800..0xDC00).contains(&code) {
+            if self.source.get(self.current..self.current + 2) != Some(&['\\', 'u']) {
+                return Err(self.error("Unpaired surrogate in unicode escape"));
+            }
+            self.current += 2;
+
+            let low = self.hex_digits()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("Unpaired surrogate in unicode escape"));
+            }
+            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+        }
+
+        // A low surrogate on its own isn't a character, so this rejects it
+        char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape"))
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.current;
+        let mut is_float = false;
+
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' | '-' | '+' => {}
+                '.' | 'e' | 'E' => is_float = true,
+                _ => break,
+            }
+            self.current += 1;
+        }
+
+        let lexeme: String = self.source[start..self.current].iter().collect();
+
+        // Same as the compiler, numbers without a decimal point are ints
+        // unless they're too big to be one
+        let int = match is_float {
+            true => None,
+            false => lexeme.parse::<i64>().ok().map(Value::Int),
+        };
+        let number = int.or_else(|| lexeme.parse::<f64>().ok().map(Value::Number));
+
+        match number {
+            Some(number) => Ok(number),
+            None => {
+                self.current = start;
+                Err(self.error(format!("Invalid number '{}'", lexeme).as_str()))
+            }
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        let end = self.current + word.len();
+        if end <= self.source.len()
+            && self.source[self.current..end]
+                .iter()
+                .copied()
+                .eq(word.chars())
+        {
+            self.current = end;
+            return Ok(value);
+        }
+
+        return Err(self.error("Unexpected character"));
+    }
+}
+
+// Objects become instances of `Object`, arrays become lists and everything
+// else maps onto the matching primitive
+pub fn parse(source: &str) -> Result<Value, String> {
+    let mut parser = JsonParser {
+        source: source.chars().collect(),
+        current: 0,
+        depth: 0,
+    };
+
+    return parser.parse();
+}
+
+// Functions, classes and anything else that isn't data can't be represented
+// in JSON, so they're an error rather than silently turning into null
+pub fn stringify(value: &Value) -> Result<String, String> {
+    let mut output = String::new();
+    write_value(value, &mut output, &mut Vec::new())?;
+
+    return Ok(output);
+}
+
+// `seen` holds the lists and instances currently being written so a
+// structure that contains itself is reported instead of recursing forever
+fn write_value(value: &Value, output: &mut String, seen: &mut Vec<usize>) -> Result<(), String> {
+    match value {
+        Value::Nil => output.push_str("null"),
+        Value::Boolean(b) => output.push_str(if *b { "true" } else { "false" }),
+        Value::Int(n) => output.push_str(n.to_string().as_str()),
+        Value::Number(n) if n.is_finite() => output.push_str(format!("{:?}", n).as_str()),
+        Value::String(s) => write_string(s, output),
+        Value::List(list) => {
+            let pointer = Rc::as_ptr(list) as usize;
+            if seen.contains(&pointer) {
+                return Err(String::from("Can't stringify a list that contains itself"));
+            }
+            seen.push(pointer);
+
+            output.push('[');
+            for (idx, value) in list.borrow().iter().enumerate() {
+                if idx > 0 {
+                    output.push(',');
+                }
+                write_value(value, output, seen)?;
+            }
+            output.push(']');
+
+            seen.pop();
+        }
+        Value::Instance(instance) => {
+            let pointer = Rc::as_ptr(instance) as usize;
+            if seen.contains(&pointer) {
+                return Err(String::from(
+                    "Can't stringify an instance that contains itself",
+                ));
+            }
+            seen.push(pointer);
+
+            // Fields are sorted so the output doesn't depend on hash order
+            let instance = instance.borrow();
+            let mut names: Vec<&String> = instance.fields.keys().collect();
+            names.sort();
+
+            output.push('{');
+            for (idx, name) in names.into_iter().enumerate() {
+                if idx > 0 {
+                    output.push(',');
+                }
+                write_string(name, output);
+                output.push(':');
+                write_value(&instance.fields[name], output, seen)?;
+            }
+            output.push('}');
+
+            seen.pop();
+        }
+        Value::Map(map) => {
+            let pointer = Rc::as_ptr(map) as usize;
+            if seen.contains(&pointer) {
+                return Err(String::from("Can't stringify a map that contains itself"));
+            }
+            seen.push(pointer);
+
+            let map = map.borrow();
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+
+            output.push('{');
+            for (idx, key) in keys.into_iter().enumerate() {
+                if idx > 0 {
+                    output.push(',');
+                }
+                write_string(key, output);
+                output.push(':');
+                write_value(&map[key], output, seen)?;
+            }
+            output.push('}');
+
+            seen.pop();
+        }
+        value => return Err(format!("Can't stringify {}", value)),
+    }
+
+    return Ok(());
+}
+
+fn write_string(s: &str, output: &mut String) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_primitives() {
+        assert!(matches!(parse("1"), Ok(Value::Int(1))));
+        assert!(matches!(parse("-2.5"), Ok(Value::Number(n)) if n == -2.5));
+        assert!(matches!(parse("1e3"), Ok(Value::Number(n)) if n == 1000.0));
+        assert!(matches!(parse(" true "), Ok(Value::Boolean(true))));
+        assert!(matches!(parse("false"), Ok(Value::Boolean(false))));
+        assert!(matches!(parse("null"), Ok(Value::Nil)));
+        assert!(matches!(parse(r#""a\"b\nA""#), Ok(Value::String(s)) if &*s == "a\"b\nA"));
+    }
+
+    #[test]
+    fn parse_big_numbers() {
+        assert!(matches!(
+            parse("9223372036854775807"),
+            Ok(Value::Int(i64::MAX))
+        ));
+        assert!(
+            matches!(parse("12345678901234567890"), Ok(Value::Number(n)) if n == 12345678901234567890.0)
+        );
+        assert!(
+            matches!(parse("-12345678901234567890"), Ok(Value::Number(n)) if n == -12345678901234567890.0)
+        );
+        assert!(matches!(parse("1e400"), Ok(Value::Number(n)) if n == f64::INFINITY));
+    }
+
+    #[test]
+    fn parse_surrogate_pairs() {
+        assert!(matches!(parse(r#""\uD83D\uDE00""#), Ok(Value::String(s)) if &*s == "\u{1F600}"));
+        assert!(matches!(parse(r#""a\u00e9""#), Ok(Value::String(s)) if &*s == "a\u{e9}"));
+
+        assert_eq!(
+            parse(r#""\uD83D""#).unwrap_err(),
+            "Unpaired surrogate in unicode escape at position 7"
+        );
+        assert_eq!(
+            parse(r#""\uD83D\u0041""#).unwrap_err(),
+            "Unpaired surrogate in unicode escape at position 13"
+        );
+        assert_eq!(
+            parse(r#""\uDE00""#).unwrap_err(),
+            "Invalid unicode escape at position 7"
+        );
+    }
+
+    #[test]
+    fn parse_nested() {
+        let value = parse(r#"{"a": 1, "b": [2, 3], "c": {}}"#).unwrap();
+
+        let Value::Instance(object) = value else {
+            panic!("Expected an instance, got {:?}", value);
+        };
+        let object = object.borrow();
+        assert_eq!(object.class.name, OBJECT_CLASS_NAME);
+        assert!(matches!(object.fields.get("a"), Some(Value::Int(1))));
+        assert!(matches!(object.fields.get("c"), Some(Value::Instance(_))));
+
+        match object.fields.get("b") {
+            Some(Value::List(list)) => {
+                let list = list.borrow();
+                assert_eq!(list.len(), 2);
+                assert!(matches!(list[0], Value::Int(2)));
+                assert!(matches!(list[1], Value::Int(3)));
+            }
+            value => panic!("Expected a list, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn parse_nesting_limit() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            parse(&nested(MAX_DEPTH + 1)).unwrap_err(),
+            format!("Nesting too deep at position {}", MAX_DEPTH)
+        );
+        assert_eq!(
+            parse(&"[{\"a\": ".repeat(20_000)).unwrap_err(),
+            format!("Nesting too deep at position {}", MAX_DEPTH / 2 * 7)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse("").unwrap_err(),
+            "Unexpected end of input at position 0"
+        );
+        assert_eq!(
+            parse("[1, 2").unwrap_err(),
+            "Expected ',' or ']' at position 5"
+        );
+        assert_eq!(
+            parse("{1: 2}").unwrap_err(),
+            "Expected string key at position 1"
+        );
+        assert_eq!(
+            parse("\"abc").unwrap_err(),
+            "Unterminated string at position 4"
+        );
+        assert_eq!(
+            parse("1 2").unwrap_err(),
+            "Unexpected trailing characters at position 2"
+        );
+        assert_eq!(
+            parse("tru").unwrap_err(),
+            "Unexpected character at position 0"
+        );
+    }
+
+    #[test]
+    fn stringify_primitives() {
+        assert_eq!(stringify(&Value::Int(42)).unwrap(), "42");
+        assert_eq!(stringify(&Value::Number(1.5)).unwrap(), "1.5");
+        assert_eq!(stringify(&Value::Number(2.0)).unwrap(), "2.0");
+        assert_eq!(stringify(&Value::Boolean(true)).unwrap(), "true");
+        assert_eq!(stringify(&Value::Nil).unwrap(), "null");
+        assert_eq!(stringify(&Value::String("hi".into())).unwrap(), "\"hi\"");
+        assert_eq!(
+            stringify(&Value::String("a\"b\\c\n".into())).unwrap(),
+            r#""a\"b\\c\n""#
+        );
+        assert!(stringify(&Value::Number(f64::NAN)).is_err());
+        assert!(stringify(&Value::Class(Rc::new(Class::new(String::from("A"))))).is_err());
+    }
+
+    #[test]
+    fn stringify_round_trip() {
+        let source = r#"{"list":[1,2.5,"three",[true,null]],"name":"x","nested":{"a":{}}}"#;
+
+        let value = parse(source).unwrap();
+        assert_eq!(stringify(&value).unwrap(), source);
+
+        let again = parse(stringify(&value).unwrap().as_str()).unwrap();
+        assert_eq!(stringify(&again).unwrap(), source);
+    }
+
+    #[test]
+    fn stringify_cycles() {
+        let list = Rc::new(RefCell::new(Vec::new()));
+        list.borrow_mut().push(Value::List(Rc::clone(&list)));
+
+        assert!(stringify(&Value::List(Rc::clone(&list))).is_err());
+
+        // The same list twice side by side isn't a cycle
+        let inner = Value::List(Rc::new(RefCell::new(vec![Value::Int(1)])));
+        let outer = Value::List(Rc::new(RefCell::new(vec![inner.clone(), inner])));
+        assert_eq!(stringify(&outer).unwrap(), "[[1],[1]]");
+
+        list.borrow_mut().clear();
+    }
+}
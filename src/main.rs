@@ -1,20 +1,28 @@
+#[cfg(test)]
+mod alloc_counter;
+mod bytecode;
 mod chunk;
 mod compiler;
 mod debug;
+mod error;
+mod json;
 mod math;
+mod peephole;
 mod scanner;
 mod value;
 mod vm;
 
 use compiler::{Compiler, FunctionType};
-use debug::print_debug::disassemble_chunk;
+use debug::print_debug::{disassemble_chunk, disassemble_function_to_string};
 use debug::write_debug::write_chunk_to_file;
 use scanner::Scanner;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
 use value::Value;
-use vm::VM;
+use vm::{InterpretResult, VM};
 
 #[allow(dead_code)]
 fn repl() {
@@ -33,7 +41,7 @@ fn repl() {
             break;
         }
 
-        let mut vm = VM::<Vec<Value>>::new();
+        let mut vm = VM::<Vec<Value>>::new().with_flush_on_print(true);
         vm.interpret(String::from(input));
 
         disassemble_chunk(&vm.chunk, "Repl chunk");
@@ -43,7 +51,7 @@ fn repl() {
     }
 }
 
-fn run_file(file_path: &str) {
+fn read_file(file_path: &str) -> String {
     let mut file =
         File::open(file_path).expect(format!("Could not open file {}", file_path).as_str());
     let mut source = String::new();
@@ -51,28 +59,35 @@ fn run_file(file_path: &str) {
     file.read_to_string(&mut source)
         .expect("Could not write file to string");
 
+    source
+}
+
+fn run_source(source: String) -> InterpretResult {
     let mut vm = VM::<Vec<Value>>::new();
+    return vm.interpret(source);
+}
+
+fn run_file(file_path: &str) {
+    let source = read_file(file_path);
 
     println!("==== BEGIN PROGRAM OUTPUT ====\n\n");
-    vm.interpret(source);
+    run_source(source);
     println!("\n\n==== END PROGRAM OUTPUT ====\n\n");
 
     // disassemble_chunk(&vm.frames[0].closure.function.chunk, "TOP LEVEL CHUNK");
 }
 
 fn debug_to_file(file_path: &str) {
-    let mut file =
-        File::open(file_path).expect(format!("Could not open file {}", file_path).as_str());
-    let mut source = String::new();
-
-    file.read_to_string(&mut source)
-        .expect("Could not write file to string");
+    let source = read_file(file_path);
 
     let scanner = Scanner::new(source.clone());
     let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
 
     let compile_result = compiler.compile(None);
     if compile_result.is_none() {
+        for error in compiler.errors() {
+            println!("{}", error);
+        }
         return;
     }
 
@@ -80,12 +95,133 @@ fn debug_to_file(file_path: &str) {
     write_chunk_to_file(source, &compiler.current_chunk(), output_path);
 }
 
+// Prints every token in the file as `line:col TokenType "lexeme"`, with the
+// reason after error tokens. Lexemes are quoted and escaped like Rust
+// strings. Nothing is compiled or run.
+fn dump_tokens(file_path: &str) {
+    let source = read_file(file_path);
+    let mut scanner = Scanner::new(source);
+
+    for token in scanner.scan_all() {
+        let lexeme = &scanner.source[token.start..(token.start + token.length)];
+        match token.error {
+            Some(error) => println!(
+                "{}:{} {:?} {:?} {}",
+                token.line, token.column, token.token_type, lexeme, error
+            ),
+            None => println!(
+                "{}:{} {:?} {:?}",
+                token.line, token.column, token.token_type, lexeme
+            ),
+        }
+    }
+}
+
+// Prints the disassembly of the whole program, nested functions included,
+// without running it
+fn dump_bytecode(file_path: &str) {
+    let source = read_file(file_path);
+
+    match Compiler::compile_source(&source) {
+        Ok(function) => print!("{}", disassemble_function_to_string(&function)),
+        Err(errors) => {
+            for error in errors {
+                println!("{}\n", error.with_source(&source));
+            }
+            process::exit(65);
+        }
+    }
+}
+
+// Compiles the file and reports every error without running anything.
+// Exits with a nonzero code if the program doesn't compile.
+fn check_file(file_path: &str) {
+    let source = read_file(file_path);
+
+    let scanner = Scanner::new(source.clone());
+    let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+
+    let compiled = compiler.compile(None).is_some();
+
+    for warning in compiler.warnings() {
+        eprintln!("{}", warning);
+    }
+
+    if compiled {
+        return;
+    }
+
+    for error in compiler.errors() {
+        println!("{}\n", error.with_source(&source));
+    }
+
+    process::exit(65);
+}
+
+// Compiles a source file to bytecode that `run` can execute later
+fn compile_file(input_path: &str, output_path: &str) {
+    let source = read_file(input_path);
+
+    let function = match Compiler::compile_source(&source) {
+        Ok(function) => function,
+        Err(errors) => {
+            for error in errors {
+                println!("{}\n", error.with_source(&source));
+            }
+            process::exit(65);
+        }
+    };
+
+    // Exit code 74 is EX_IOERR
+    if let Err(e) = fs::write(output_path, bytecode::serialize_program(&function)) {
+        eprintln!("Couldn't write {}: {}", output_path, e);
+        process::exit(74);
+    }
+}
+
+fn run_compiled_file(file_path: &str) {
+    // Exit code 66 is EX_NOINPUT
+    let bytes = match fs::read(file_path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Couldn't open {}: {}", file_path, e);
+            process::exit(66);
+        }
+    };
+
+    let function = match bytecode::deserialize_program(&bytes) {
+        Ok(function) => function,
+        Err(message) => {
+            println!("Couldn't load {}: {}", file_path, message);
+            process::exit(65);
+        }
+    };
+
+    let mut vm = VM::<Vec<Value>>::new();
+    match vm.interpret_function(function) {
+        InterpretResult::Ok => {}
+        InterpretResult::CompileError => process::exit(65),
+        InterpretResult::RuntimeError => process::exit(70),
+    }
+}
+
+const USAGE: &str = "rlox [repl | file [<file>] | - | check [<file>] | debug [<file>] \
+    | compile <in> <out> | run <file.loxc> | --dump-tokens <file> | --dump-bytecode <file> | <file>]";
+
+// Exit code 64 is EX_USAGE, for when the command line itself is wrong
+fn usage_error(usage: &str) -> ! {
+    eprintln!("Usage: {}", usage);
+    process::exit(64);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    // assert!(args.len() >= 2);
 
-    // let mode = &args[1];
-    let mode = String::from("file");
+    let mode = if args.len() >= 2 {
+        args[1].clone()
+    } else {
+        String::from("file")
+    };
     match mode.as_str() {
         "repl" => {
             repl();
@@ -97,6 +233,55 @@ fn main() {
                 run_file("./data/test.rlox");
             }
         }
+        // `echo 'println 1 + 1;' | rlox -` runs whatever is piped in. Only the
+        // program's own output is printed so it can be piped on again.
+        "-" => {
+            let mut source = String::new();
+            io::stdin()
+                .read_to_string(&mut source)
+                .expect("Could not read program from stdin");
+
+            match run_source(source) {
+                InterpretResult::Ok => {}
+                InterpretResult::CompileError => process::exit(65),
+                InterpretResult::RuntimeError => process::exit(70),
+            }
+        }
+        "--dump-tokens" => {
+            if args.len() >= 3 {
+                dump_tokens(&args[2]);
+            } else {
+                usage_error("rlox --dump-tokens <file>");
+            }
+        }
+        "--dump-bytecode" => {
+            if args.len() >= 3 {
+                dump_bytecode(&args[2]);
+            } else {
+                usage_error("rlox --dump-bytecode <file>");
+            }
+        }
+        "check" => {
+            if args.len() >= 3 {
+                check_file(&args[2]);
+            } else {
+                check_file("./data/test.rlox");
+            }
+        }
+        "compile" => {
+            if args.len() >= 4 {
+                compile_file(&args[2], &args[3]);
+            } else {
+                usage_error("rlox compile <in> <out>");
+            }
+        }
+        "run" => {
+            if args.len() >= 3 {
+                run_compiled_file(&args[2]);
+            } else {
+                usage_error("rlox run <file.loxc>");
+            }
+        }
         "debug" => {
             if args.len() >= 3 {
                 debug_to_file(&args[2]);
@@ -104,8 +289,13 @@ fn main() {
                 debug_to_file("./data/test.rlox");
             }
         }
+        // `rlox script.lox` is short for `rlox file script.lox`
+        path if args.len() == 2 && Path::new(path).is_file() => {
+            run_file(path);
+        }
         _ => {
-            panic!("Unsupported mode: {mode}");
+            eprintln!("Unsupported mode: {}", mode);
+            usage_error(USAGE);
         }
     }
 }
@@ -1,9 +1,11 @@
-struct Term {
+#[derive(Debug, PartialEq)]
+pub struct Term {
     coefficient: f64,
     power: i32,
 }
 
-struct Polynomial {
+#[derive(Debug)]
+pub struct Polynomial {
     terms: Vec<Term>,
 }
 
@@ -25,20 +27,69 @@ impl PolynomialParser {
     fn parse_polynomial(&self) -> Result<Polynomial, String> {
         let mut terms = Vec::new();
 
-        for term in self.expression.split_whitespace() {
-            println!("{}", term);
+        let expression: String = self.expression.split_whitespace().collect();
+        if expression.is_empty() {
+            return Err(String::from("Polynomial is empty"));
         }
 
+        // Each term starts at a sign, except for a negative power like `x^-1`
+        let mut start = 0;
+        let mut previous = None;
+        for (idx, c) in expression.char_indices() {
+            if idx > 0 && (c == '+' || c == '-') && previous != Some('^') {
+                terms.push(PolynomialParser::parse_term(&expression[start..idx])?);
+                start = idx;
+            }
+            previous = Some(c);
+        }
+        terms.push(PolynomialParser::parse_term(&expression[start..])?);
+
         return Ok(Polynomial { terms });
     }
+
+    // Parses terms like `3x^2`, `-x` and `5`, where a missing coefficient
+    // is 1 and a missing power is 1 (or 0 without an `x`)
+    fn parse_term(term: &str) -> Result<Term, String> {
+        let (sign, unsigned) = match term.chars().next() {
+            Some('-') => (-1.0, &term[1..]),
+            Some('+') => (1.0, &term[1..]),
+            _ => (1.0, term),
+        };
+
+        let (coefficient, power) = match unsigned.split_once('x') {
+            Some((coefficient, power)) => {
+                let power = match power {
+                    "" => 1,
+                    _ => match power.strip_prefix('^') {
+                        Some(power) => power
+                            .parse::<i32>()
+                            .map_err(|_| format!("Invalid power in term '{}'", term))?,
+                        None => return Err(format!("Invalid term '{}'", term)),
+                    },
+                };
+
+                (coefficient.strip_suffix('*').unwrap_or(coefficient), power)
+            }
+            None => (unsigned, 0),
+        };
+
+        let coefficient = match coefficient {
+            "" if unsigned.contains('x') => 1.0,
+            _ => coefficient
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid coefficient in term '{}'", term))?,
+        };
+
+        return Ok(Term {
+            coefficient: sign * coefficient,
+            power,
+        });
+    }
 }
 
-// TODO: Consider renaming this
-pub fn parse_polynomial(expression: String) {
+pub fn parse_polynomial(expression: String) -> Result<Polynomial, String> {
     let parser = PolynomialParser { expression };
-    let polynomial = parser.parse_polynomial();
-
-    println!("{}", polynomial.unwrap().compute(1.0));
+    return parser.parse_polynomial();
 }
 
 #[cfg(test)]
@@ -46,7 +97,69 @@ mod tests {
     use super::*;
 
     #[test]
-    fn wip() {
-        parse_polynomial(String::from("x^2 + x - 1"));
+    fn parse_terms() {
+        let polynomial = parse_polynomial(String::from("x^2 + x - 1")).unwrap();
+
+        assert_eq!(
+            polynomial.terms,
+            vec![
+                Term {
+                    coefficient: 1.0,
+                    power: 2
+                },
+                Term {
+                    coefficient: 1.0,
+                    power: 1
+                },
+                Term {
+                    coefficient: -1.0,
+                    power: 0
+                },
+            ]
+        );
+
+        let polynomial = parse_polynomial(String::from("3x^2 -x + 5 + 2*x^-1")).unwrap();
+
+        assert_eq!(
+            polynomial.terms,
+            vec![
+                Term {
+                    coefficient: 3.0,
+                    power: 2
+                },
+                Term {
+                    coefficient: -1.0,
+                    power: 1
+                },
+                Term {
+                    coefficient: 5.0,
+                    power: 0
+                },
+                Term {
+                    coefficient: 2.0,
+                    power: -1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluate() {
+        let polynomial = parse_polynomial(String::from("x^2 + x - 1")).unwrap();
+
+        assert_eq!(polynomial.compute(0.0), -1.0);
+        assert_eq!(polynomial.compute(1.0), 1.0);
+        assert_eq!(polynomial.compute(2.0), 5.0);
+        assert_eq!(polynomial.compute(-3.0), 5.0);
+        assert_eq!(polynomial.compute(0.5), -0.25);
+    }
+
+    #[test]
+    fn invalid_polynomials() {
+        assert!(parse_polynomial(String::from("")).is_err());
+        assert!(parse_polynomial(String::from("x^")).is_err());
+        assert!(parse_polynomial(String::from("3y")).is_err());
+        assert!(parse_polynomial(String::from("x2")).is_err());
+        assert!(parse_polynomial(String::from("x +")).is_err());
     }
 }
@@ -0,0 +1,243 @@
+use crate::{
+    chunk::{Chunk, OpCode},
+    value::Value,
+};
+
+// `!=` compiles to OP_EQUAL followed by OP_NOT, and so does `!(a == b)`.
+// Once a function is done compiling, each of those pairs is collapsed into a
+// single instruction so the VM only dispatches once. `!(a < b)` is left
+// alone, it isn't the same as `a >= b` when NaN is involved.
+fn fused(first: u8, second: u8) -> Option<OpCode> {
+    if second != OpCode::Not as u8 {
+        return None;
+    }
+
+    match OpCode::from_u8(first) {
+        Some(OpCode::Equal) => return Some(OpCode::NotEqual),
+        _ => return None,
+    }
+}
+
+// Number of bytes taken up by the instruction at `offset`, operands included
+pub fn instruction_length(chunk: &Chunk, offset: usize) -> usize {
+    match OpCode::from_u8(chunk.code[offset]) {
+        Some(
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::BuildList
+            | OpCode::BuildMap
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Class
+            | OpCode::GetProperty
+            | OpCode::GetSuper
+            | OpCode::SetProperty
+            | OpCode::Method,
+        ) => return 2,
+        Some(OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop) => return 3,
+        // Each upvalue adds an (is_local, index) pair after the constant
+        Some(OpCode::Closure) => match &chunk.constants[chunk.code[offset + 1] as usize] {
+            Value::Function(function) => return 2 + 2 * function.upvalue_count as usize,
+            _ => return 2,
+        },
+        _ => return 1,
+    }
+}
+
+pub fn read_short(code: &[u8], offset: usize) -> usize {
+    return ((code[offset] as usize) << 8) | code[offset + 1] as usize;
+}
+
+// Where the jump or loop at `offset` lands, if it is one
+fn jump_target(chunk: &Chunk, offset: usize) -> Option<usize> {
+    match OpCode::from_u8(chunk.code[offset]) {
+        Some(OpCode::Jump | OpCode::JumpIfFalse) => {
+            return Some(offset + 3 + read_short(&chunk.code, offset + 1))
+        }
+        Some(OpCode::Loop) => return Some(offset + 3 - read_short(&chunk.code, offset + 1)),
+        _ => return None,
+    }
+}
+
+// Writes `byte` with the line and span of whatever was at `offset` before
+fn copy_byte(optimized: &mut Chunk, chunk: &Chunk, lines: &[usize], byte: u8, offset: usize) {
+    match chunk.span_at(offset) {
+        Some(span) => optimized.write_code_with_span(byte, lines[offset], span),
+        None => optimized.write_code(byte, lines[offset]),
+    }
+}
+
+pub fn optimize(chunk: &mut Chunk) {
+    let mut starts = Vec::new();
+    let mut targets = Vec::new();
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        starts.push(offset);
+        if let Some(target) = jump_target(chunk, offset) {
+            targets.push(target);
+        }
+        offset += instruction_length(chunk, offset);
+    }
+
+    let lines: Vec<usize> = chunk.byte_lines().collect();
+    let mut optimized = Chunk::new();
+
+    // Old offset -> new offset, so jumps can be pointed at the same
+    // instructions once everything has moved
+    let mut new_offsets = vec![0; chunk.code.len() + 1];
+    let mut jumps = Vec::new();
+
+    let mut idx = 0;
+    while idx < starts.len() {
+        let start = starts[idx];
+        let length = instruction_length(chunk, start);
+        new_offsets[start] = optimized.code.len();
+
+        // Nothing can jump between the two halves of a pair we collapse
+        let next = start + length;
+        let fusion = match chunk.code.get(next) {
+            Some(second) if length == 1 && !targets.contains(&next) => {
+                fused(chunk.code[start], *second)
+            }
+            _ => None,
+        };
+
+        match fusion {
+            Some(op) => {
+                new_offsets[next] = optimized.code.len();
+                copy_byte(&mut optimized, chunk, &lines, op as u8, start);
+                idx += 2;
+            }
+            None => {
+                if jump_target(chunk, start).is_some() {
+                    jumps.push((start, optimized.code.len()));
+                }
+                for offset in start..next {
+                    copy_byte(&mut optimized, chunk, &lines, chunk.code[offset], offset);
+                }
+                idx += 1;
+            }
+        }
+    }
+    new_offsets[chunk.code.len()] = optimized.code.len();
+
+    for (old_offset, new_offset) in jumps {
+        let target = new_offsets[jump_target(chunk, old_offset).unwrap()];
+        let distance = if target > new_offset {
+            target - (new_offset + 3)
+        } else {
+            (new_offset + 3) - target
+        };
+
+        optimized.code[new_offset + 1] = ((distance >> 8) & 0xff) as u8;
+        optimized.code[new_offset + 2] = (distance & 0xff) as u8;
+    }
+
+    chunk.code = optimized.code;
+    chunk.lines = optimized.lines;
+    chunk.spans = optimized.spans;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(instructions: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for (line, byte) in instructions.iter().enumerate() {
+            chunk.write_code(*byte, line + 1);
+        }
+        chunk
+    }
+
+    #[test]
+    fn collapses_comparison_pairs() {
+        let mut chunk = chunk(&[
+            OpCode::Equal as u8,
+            OpCode::Not as u8,
+            OpCode::Less as u8,
+            OpCode::Not as u8,
+            OpCode::Greater as u8,
+            OpCode::Not as u8,
+            OpCode::Not as u8,
+            OpCode::Return as u8,
+        ]);
+        optimize(&mut chunk);
+
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::NotEqual as u8,
+                OpCode::Less as u8,
+                OpCode::Not as u8,
+                OpCode::Greater as u8,
+                OpCode::Not as u8,
+                OpCode::Not as u8,
+                OpCode::Return as u8,
+            ]
+        );
+        let lines: Vec<usize> = (0..chunk.code.len())
+            .map(|offset| chunk.line_at(offset))
+            .collect();
+        assert_eq!(lines, vec![1, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn jumps_are_retargeted() {
+        // JumpIfFalse skips over a collapsed pair, Loop goes back over one
+        let mut chunk = chunk(&[
+            OpCode::Equal as u8,
+            OpCode::Not as u8,
+            OpCode::JumpIfFalse as u8,
+            0,
+            2,
+            OpCode::Equal as u8,
+            OpCode::Not as u8,
+            OpCode::Loop as u8,
+            0,
+            10,
+            OpCode::Return as u8,
+        ]);
+        optimize(&mut chunk);
+
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::NotEqual as u8,
+                OpCode::JumpIfFalse as u8,
+                0,
+                1,
+                OpCode::NotEqual as u8,
+                OpCode::Loop as u8,
+                0,
+                8,
+                OpCode::Return as u8,
+            ]
+        );
+        assert_eq!(jump_target(&chunk, 1), Some(5));
+        assert_eq!(jump_target(&chunk, 5), Some(0));
+    }
+
+    #[test]
+    fn jump_targets_are_not_collapsed() {
+        // Something lands on the Not, so it has to stay its own instruction
+        let mut chunk = chunk(&[
+            OpCode::Jump as u8,
+            0,
+            1,
+            OpCode::Equal as u8,
+            OpCode::Not as u8,
+            OpCode::Return as u8,
+        ]);
+        let code = chunk.code.clone();
+        optimize(&mut chunk);
+
+        assert_eq!(chunk.code, code);
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub enum TokenType {
     // Single-character tokens.
@@ -5,6 +7,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -12,6 +17,9 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Ampersand,
+    Pipe,
+    Caret,
 
     // One or two character tokens.
     Bang,
@@ -22,6 +30,13 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    StarStar,
 
     // Literals.
     Identifier,
@@ -31,14 +46,19 @@ pub enum TokenType {
     // Keywords.
     And,
     Class,
+    Const,
+    Do,
     Else,
     False,
     For,
     Fun,
     If,
+    In,
+    Is,
     Nil,
     Or,
     Print,
+    Println,
     Return,
     Super,
     This,
@@ -47,6 +67,7 @@ pub enum TokenType {
     While,
 
     // Misc.
+    Comment,
     Error,
     Eof,
 }
@@ -57,6 +78,25 @@ pub struct Token {
     pub start: usize,
     pub length: usize,
     pub line: usize,
+    pub column: usize,
+
+    // What went wrong, for error tokens
+    pub error: Option<ScanError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanError {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'.", c),
+            ScanError::UnterminatedString => write!(f, "Unterminated string literal."),
+        }
+    }
 }
 
 impl Token {
@@ -66,6 +106,8 @@ impl Token {
             start: 0,
             length: 0,
             line: 0,
+            column: 0,
+            error: None,
         }
     }
 }
@@ -78,12 +120,35 @@ fn is_digit(c: char) -> bool {
     c >= '0' && c <= '9'
 }
 
+// Where a scanner is in its source, so it can be rewound after looking ahead
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint {
+    start: usize,
+    current: usize,
+    line: usize,
+    line_start: usize,
+    column: usize,
+    finished: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Scanner {
     pub source: String,
     start: usize,
     current: usize,
     line: usize,
+
+    // Offset of the first character on the current line, and the
+    // 0-based column of the token currently being scanned
+    line_start: usize,
+    column: usize,
+
+    // When set, comments are returned as `TokenType::Comment` tokens
+    // instead of being skipped along with the whitespace
+    emit_comments: bool,
+
+    // Set once the iterator has handed out Eof
+    finished: bool,
 }
 
 impl Scanner {
@@ -93,36 +158,82 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 0,
+            emit_comments: false,
+            finished: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn new_with_comments(source: String) -> Self {
+        let mut scanner = Scanner::new(source);
+        scanner.emit_comments = true;
+        scanner
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            start: self.start,
+            current: self.current,
+            line: self.line,
+            line_start: self.line_start,
+            column: self.column,
+            finished: self.finished,
         }
     }
 
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.start = checkpoint.start;
+        self.current = checkpoint.current;
+        self.line = checkpoint.line;
+        self.line_start = checkpoint.line_start;
+        self.column = checkpoint.column;
+        self.finished = checkpoint.finished;
+    }
+
     fn make_token(&self, token_type: TokenType) -> Token {
         Token {
             token_type,
             start: self.start,
             length: self.current - self.start,
             line: self.line,
+            column: self.column,
+            error: None,
         }
     }
 
+    fn error_token(&self, error: ScanError) -> Token {
+        let mut token = self.make_token(TokenType::Error);
+        token.error = Some(error);
+        token
+    }
+
+    // Called while positioned on a newline character, before advancing
+    // past it
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current + 1;
+    }
+
     fn is_at_end(&self) -> bool {
         self.current == self.source.len()
     }
 
-    // This will probably be incredibly slow over time since it converts
-    // the source to a list of chars every time. It may be more economical
-    // to just instantiate a vector of chars when the `new` func is called.
+    // Token positions are byte offsets into the source (that's what the
+    // compiler slices with), so characters are decoded starting at a byte
+    // offset rather than counted from the start
     fn get_char_at_index(&self, index: usize) -> char {
-        return self
-            .source
+        return self.source[index..]
             .chars()
-            .nth(index)
+            .next()
             .expect(format!("Couldn't get char at index {}", index).as_str());
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        return self.get_char_at_index(self.current - 1);
+        let c = self.get_char_at_index(self.current);
+        self.current += c.len_utf8();
+        return c;
     }
 
     fn peek(&self) -> char {
@@ -138,7 +249,12 @@ impl Scanner {
             return None;
         }
 
-        return Some(self.get_char_at_index(self.current + 1));
+        let next = self.current + self.peek().len_utf8();
+        if next >= self.source.len() {
+            return None;
+        }
+
+        return Some(self.get_char_at_index(next));
     }
 
     fn match_char(&mut self, expected: char) -> bool {
@@ -166,14 +282,15 @@ impl Scanner {
                     self.advance();
                 }
                 '\n' => {
-                    self.line += 1;
+                    self.new_line();
                     self.advance();
                 }
                 '/' => match self.peek_next() {
-                    Some('/') => {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
+                    Some('/') if !self.emit_comments => {
+                        self.line_comment();
+                    }
+                    Some('*') if !self.emit_comments => {
+                        self.block_comment();
                     }
                     _ => break,
                 },
@@ -184,6 +301,31 @@ impl Scanner {
         }
     }
 
+    // Consumes everything up to (but not including) the next newline
+    fn line_comment(&mut self) {
+        while self.peek() != '\n' && !self.is_at_end() {
+            self.advance();
+        }
+    }
+
+    // Consumes a `/* ... */` comment, including both delimiters. An
+    // unterminated block comment runs to the end of the source.
+    fn block_comment(&mut self) {
+        self.current += 2;
+
+        while !self.is_at_end() {
+            if self.peek() == '*' && self.peek_next() == Some('/') {
+                self.current += 2;
+                return;
+            }
+
+            if self.peek() == '\n' {
+                self.new_line();
+            }
+            self.advance();
+        }
+    }
+
     fn check_keyword(
         &self,
         start: usize,
@@ -202,27 +344,28 @@ impl Scanner {
     }
 
     fn identifier_type(&self) -> TokenType {
-        let c = self.source.chars().nth(self.start).expect(
-            format!(
-                "Expected to be able to get char at index {} in source",
-                self.start
-            )
-            .as_str(),
-        );
+        let c = self.get_char_at_index(self.start);
 
         return match c {
             'a' => self.check_keyword(1, 2, "nd", TokenType::And),
-            'c' => self.check_keyword(1, 4, "lass", TokenType::Class),
+            'c' => {
+                if self.current - self.start > 1 {
+                    let c2 = self.get_char_at_index(self.start + 1);
+
+                    return match c2 {
+                        'l' => self.check_keyword(2, 3, "ass", TokenType::Class),
+                        'o' => self.check_keyword(2, 3, "nst", TokenType::Const),
+                        _ => TokenType::Identifier,
+                    };
+                } else {
+                    return TokenType::Identifier;
+                }
+            }
+            'd' => self.check_keyword(1, 1, "o", TokenType::Do),
             'e' => self.check_keyword(1, 3, "lse", TokenType::Else),
             'f' => {
                 if self.current - self.start > 1 {
-                    let c2 = self.source.chars().nth(self.start + 1).expect(
-                        format!(
-                            "Expected to be able to get char at index {} in source",
-                            self.start + 1
-                        )
-                        .as_str(),
-                    );
+                    let c2 = self.get_char_at_index(self.start + 1);
 
                     return match c2 {
                         'a' => self.check_keyword(2, 3, "lse", TokenType::False),
@@ -234,21 +377,24 @@ impl Scanner {
                     return TokenType::Identifier;
                 }
             }
-            'i' => self.check_keyword(1, 1, "f", TokenType::If),
+            'i' => match self.check_keyword(1, 1, "f", TokenType::If) {
+                TokenType::Identifier => match self.check_keyword(1, 1, "n", TokenType::In) {
+                    TokenType::Identifier => self.check_keyword(1, 1, "s", TokenType::Is),
+                    token_type => token_type,
+                },
+                token_type => token_type,
+            },
             'n' => self.check_keyword(1, 2, "il", TokenType::Nil),
             'o' => self.check_keyword(1, 1, "r", TokenType::Or),
-            'p' => self.check_keyword(1, 4, "rint", TokenType::Print),
+            'p' => match self.check_keyword(1, 4, "rint", TokenType::Print) {
+                TokenType::Identifier => self.check_keyword(1, 6, "rintln", TokenType::Println),
+                token_type => token_type,
+            },
             'r' => self.check_keyword(1, 5, "eturn", TokenType::Return),
             's' => self.check_keyword(1, 4, "uper", TokenType::Super),
             't' => {
                 if self.current - self.start > 1 {
-                    let c2 = self.source.chars().nth(self.start + 1).expect(
-                        format!(
-                            "Expected to be able to get char at index {} in source",
-                            self.start + 1
-                        )
-                        .as_str(),
-                    );
+                    let c2 = self.get_char_at_index(self.start + 1);
 
                     return match c2 {
                         'h' => self.check_keyword(2, 2, "is", TokenType::This),
@@ -297,15 +443,34 @@ impl Scanner {
     }
 
     fn string(&mut self) -> Token {
+        // Strings can span lines, but an unterminated one is reported where
+        // it was opened
+        let line = self.line;
+
         loop {
             if self.is_at_end() {
-                return self.make_token(TokenType::Error);
+                let mut token = self.error_token(ScanError::UnterminatedString);
+                token.line = line;
+                return token;
             }
 
             let c = self.peek();
 
             if c == '\n' {
-                self.line += 1;
+                self.new_line();
+            }
+
+            // An escaped quote doesn't end the string. The compiler decodes
+            // escapes, the scanner only has to step over them.
+            if c == '\\' {
+                self.advance();
+                if !self.is_at_end() {
+                    if self.peek() == '\n' {
+                        self.new_line();
+                    }
+                    self.advance();
+                }
+                continue;
             }
 
             if c != '"' {
@@ -319,9 +484,25 @@ impl Scanner {
         return self.make_token(TokenType::String);
     }
 
+    // Every remaining token, up to and including the first Eof. Error tokens
+    // are kept, scanning always moves past them.
+    pub fn scan_all(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let token = self.scan_token();
+            tokens.push(token);
+
+            if let TokenType::Eof = token.token_type {
+                return tokens;
+            }
+        }
+    }
+
     pub fn scan_token(&mut self) -> Token {
         self.skip_whitespace();
         self.start = self.current;
+        self.column = self.start - self.line_start;
 
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
@@ -341,13 +522,53 @@ impl Scanner {
             ')' => return self.make_token(TokenType::RightParen),
             '{' => return self.make_token(TokenType::LeftBrace),
             '}' => return self.make_token(TokenType::RightBrace),
+            '[' => return self.make_token(TokenType::LeftBracket),
+            ']' => return self.make_token(TokenType::RightBracket),
             ';' => return self.make_token(TokenType::Semicolon),
+            ':' => return self.make_token(TokenType::Colon),
             ',' => return self.make_token(TokenType::Comma),
             '.' => return self.make_token(TokenType::Dot),
-            '-' => return self.make_token(TokenType::Minus),
-            '+' => return self.make_token(TokenType::Plus),
-            '/' => return self.make_token(TokenType::Slash),
-            '*' => return self.make_token(TokenType::Star),
+            '-' => {
+                if self.match_char('=') {
+                    return self.make_token(TokenType::MinusEqual);
+                }
+                return self.make_token(TokenType::Minus);
+            }
+            '+' => {
+                if self.match_char('=') {
+                    return self.make_token(TokenType::PlusEqual);
+                }
+                return self.make_token(TokenType::Plus);
+            }
+            '/' => {
+                if self.emit_comments && self.peek() == '/' {
+                    self.line_comment();
+                    return self.make_token(TokenType::Comment);
+                }
+                if self.emit_comments && self.peek() == '*' {
+                    // `block_comment` expects to start on the opening `/`
+                    self.current -= 1;
+                    self.block_comment();
+                    return self.make_token(TokenType::Comment);
+                }
+
+                if self.match_char('=') {
+                    return self.make_token(TokenType::SlashEqual);
+                }
+                return self.make_token(TokenType::Slash);
+            }
+            '*' => {
+                if self.match_char('*') {
+                    return self.make_token(TokenType::StarStar);
+                }
+                if self.match_char('=') {
+                    return self.make_token(TokenType::StarEqual);
+                }
+                return self.make_token(TokenType::Star);
+            }
+            '&' => return self.make_token(TokenType::Ampersand),
+            '|' => return self.make_token(TokenType::Pipe),
+            '^' => return self.make_token(TokenType::Caret),
 
             '!' => {
                 if self.match_char('=') {
@@ -366,6 +587,8 @@ impl Scanner {
             '<' => {
                 if self.match_char('=') {
                     return self.make_token(TokenType::LessEqual);
+                } else if self.match_char('<') {
+                    return self.make_token(TokenType::LessLess);
                 } else {
                     return self.make_token(TokenType::Less);
                 }
@@ -373,6 +596,8 @@ impl Scanner {
             '>' => {
                 if self.match_char('=') {
                     return self.make_token(TokenType::GreaterEqual);
+                } else if self.match_char('>') {
+                    return self.make_token(TokenType::GreaterGreater);
                 } else {
                     return self.make_token(TokenType::Greater);
                 }
@@ -380,11 +605,29 @@ impl Scanner {
 
             '"' => return self.string(),
 
-            _ => return self.make_token(TokenType::Error),
+            c => return self.error_token(ScanError::UnexpectedCharacter(c)),
         }
     }
 }
 
+// Yields tokens up to and including Eof, then stops
+impl Iterator for Scanner {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+
+        let token = self.scan_token();
+        if let TokenType::Eof = token.token_type {
+            self.finished = true;
+        }
+
+        return Some(token);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -414,14 +657,18 @@ mod tests {
         let keywords_to_enum = HashMap::from([
             ("and", TokenType::And),
             ("class", TokenType::Class),
+            ("const", TokenType::Const),
+            ("do", TokenType::Do),
             ("else", TokenType::Else),
             ("false", TokenType::False),
             ("for", TokenType::For),
             ("fun", TokenType::Fun),
             ("if", TokenType::If),
+            ("is", TokenType::Is),
             ("nil", TokenType::Nil),
             ("or", TokenType::Or),
             ("print", TokenType::Print),
+            ("println", TokenType::Println),
             ("return", TokenType::Return),
             ("super", TokenType::Super),
             ("this", TokenType::This),
@@ -473,4 +720,288 @@ mod tests {
         assert_eq!(five.length, 1);
         assert_eq!(hello_string.length, 7);
     }
+
+    #[test]
+    fn comments_are_skipped_by_default() {
+        let source = String::from("// line\n/* block\ncomment */ 1");
+        let mut scanner = Scanner::new(source);
+
+        let one = scanner.scan_token();
+        let eof = scanner.scan_token();
+
+        assert_eq!(one.token_type as u8, TokenType::Number as u8);
+        assert_eq!(one.line, 3);
+        assert_eq!(eof.token_type as u8, TokenType::Eof as u8);
+    }
+
+    #[test]
+    fn comments_as_tokens() {
+        let source = String::from("1 // line\n/* a\nb */ 2 / 3");
+        let mut scanner = Scanner::new_with_comments(source);
+
+        let one = scanner.scan_token();
+        let line_comment = scanner.scan_token();
+        let block_comment = scanner.scan_token();
+        let two = scanner.scan_token();
+        let slash = scanner.scan_token();
+        let three = scanner.scan_token();
+
+        assert_eq!(one.token_type as u8, TokenType::Number as u8);
+
+        assert_eq!(line_comment.token_type as u8, TokenType::Comment as u8);
+        assert_eq!(line_comment.start, 2);
+        assert_eq!(line_comment.length, 7);
+        assert_eq!(line_comment.line, 1);
+
+        assert_eq!(block_comment.token_type as u8, TokenType::Comment as u8);
+        assert_eq!(block_comment.start, 10);
+        assert_eq!(block_comment.length, 9);
+        assert_eq!(block_comment.line, 3);
+
+        assert_eq!(two.token_type as u8, TokenType::Number as u8);
+        assert_eq!(slash.token_type as u8, TokenType::Slash as u8);
+        assert_eq!(three.token_type as u8, TokenType::Number as u8);
+    }
+
+    #[test]
+    fn restoring_a_checkpoint_rewinds() {
+        let mut scanner = Scanner::new(String::from("a\n  in b"));
+        scanner.scan_token();
+
+        let checkpoint = scanner.checkpoint();
+        let peeked = scanner.scan_token();
+        scanner.restore(checkpoint);
+        let scanned = scanner.scan_token();
+
+        assert_eq!(peeked.token_type as u8, TokenType::In as u8);
+        assert_eq!(scanned.token_type as u8, TokenType::In as u8);
+        assert_eq!(scanned.start, peeked.start);
+        assert_eq!(scanned.line, 2);
+        assert_eq!(scanned.column, 2);
+    }
+
+    #[test]
+    fn columns() {
+        let source = String::from("var a = 1;\n  print a;");
+        let mut scanner = Scanner::new(source);
+
+        let var = scanner.scan_token();
+        let a = scanner.scan_token();
+        for _ in 0..3 {
+            scanner.scan_token();
+        }
+        let print = scanner.scan_token();
+        let second_a = scanner.scan_token();
+
+        assert_eq!(var.column, 0);
+        assert_eq!(a.column, 4);
+
+        assert_eq!(print.line, 2);
+        assert_eq!(print.column, 2);
+        assert_eq!(second_a.line, 2);
+        assert_eq!(second_a.column, 8);
+    }
+
+    #[test]
+    fn bitwise_tokens() {
+        let source = String::from("& | ^ << >> < <= > >=");
+        let mut scanner = Scanner::new(source);
+
+        let expected = [
+            TokenType::Ampersand,
+            TokenType::Pipe,
+            TokenType::Caret,
+            TokenType::LessLess,
+            TokenType::GreaterGreater,
+            TokenType::Less,
+            TokenType::LessEqual,
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Eof,
+        ];
+
+        for token_type in expected {
+            assert_eq!(scanner.scan_token().token_type as u8, token_type as u8);
+        }
+    }
+
+    #[test]
+    fn scan_all() {
+        let mut scanner = Scanner::new(String::from("1 + 2"));
+        let token_types: Vec<u8> = scanner
+            .scan_all()
+            .iter()
+            .map(|token| token.token_type as u8)
+            .collect();
+
+        assert_eq!(
+            token_types,
+            vec![
+                TokenType::Number as u8,
+                TokenType::Plus as u8,
+                TokenType::Number as u8,
+                TokenType::Eof as u8,
+            ]
+        );
+
+        // Errors don't stop it, and it stops at the end of an unterminated string
+        let mut scanner = Scanner::new(String::from("@ \"abc"));
+        let tokens = scanner.scan_all();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].token_type as u8, TokenType::Error as u8);
+        assert_eq!(tokens[2].token_type as u8, TokenType::Eof as u8);
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let mut scanner = Scanner::new(String::from("var s =\n  \"abc\nd"));
+        let token = scanner.scan_all()[3];
+
+        assert_eq!(token.token_type as u8, TokenType::Error as u8);
+        assert_eq!(token.error, Some(ScanError::UnterminatedString));
+        assert_eq!((token.line, token.column, token.start), (2, 2, 10));
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        let mut scanner = Scanner::new(String::from(r#""say \"hi\"" "\\" 1"#));
+        let types: Vec<u8> = scanner
+            .scan_all()
+            .iter()
+            .map(|token| token.token_type as u8)
+            .collect();
+
+        assert_eq!(
+            types,
+            vec![
+                TokenType::String as u8,
+                TokenType::String as u8,
+                TokenType::Number as u8,
+                TokenType::Eof as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let mut scanner = Scanner::new(String::from("1 @ 2"));
+        let token = scanner.scan_all()[1];
+
+        assert_eq!(token.token_type as u8, TokenType::Error as u8);
+        assert_eq!(token.error, Some(ScanError::UnexpectedCharacter('@')));
+        assert_eq!(
+            token.error.unwrap().to_string(),
+            "Unexpected character '@'."
+        );
+    }
+
+    #[test]
+    fn iterator() {
+        let mut scanner = Scanner::new(String::from("fun f(){}"));
+        let token_types: Vec<u8> = scanner
+            .by_ref()
+            .map(|token| token.token_type as u8)
+            .collect();
+
+        assert_eq!(
+            token_types,
+            vec![
+                TokenType::Fun as u8,
+                TokenType::Identifier as u8,
+                TokenType::LeftParen as u8,
+                TokenType::RightParen as u8,
+                TokenType::LeftBrace as u8,
+                TokenType::RightBrace as u8,
+                TokenType::Eof as u8,
+            ]
+        );
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn compound_assignment_tokens() {
+        let source = String::from("+= -= *= /= + - * /");
+        let mut scanner = Scanner::new(source);
+
+        let expected = [
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::Plus,
+            TokenType::Minus,
+            TokenType::Star,
+            TokenType::Slash,
+            TokenType::Eof,
+        ];
+
+        for token_type in expected {
+            assert_eq!(scanner.scan_token().token_type as u8, token_type as u8);
+        }
+    }
+
+    #[test]
+    fn star_star_token() {
+        let source = String::from("2 ** 3 * 4 *= 5");
+        let mut scanner = Scanner::new(source);
+
+        let expected = [
+            TokenType::Number,
+            TokenType::StarStar,
+            TokenType::Number,
+            TokenType::Star,
+            TokenType::Number,
+            TokenType::StarEqual,
+            TokenType::Number,
+            TokenType::Eof,
+        ];
+
+        for token_type in expected {
+            assert_eq!(scanner.scan_token().token_type as u8, token_type as u8);
+        }
+    }
+
+    #[test]
+    fn bracket_tokens() {
+        let source = String::from("xs[0] = [1];");
+        let mut scanner = Scanner::new(source);
+
+        let expected = [
+            TokenType::Identifier,
+            TokenType::LeftBracket,
+            TokenType::Number,
+            TokenType::RightBracket,
+            TokenType::Equal,
+            TokenType::LeftBracket,
+            TokenType::Number,
+            TokenType::RightBracket,
+            TokenType::Semicolon,
+            TokenType::Eof,
+        ];
+
+        for token_type in expected {
+            assert_eq!(scanner.scan_token().token_type as u8, token_type as u8);
+        }
+    }
+
+    #[test]
+    fn non_ascii_source() {
+        let source = String::from("\"héllo\" + ünïcode;");
+        let mut scanner = Scanner::new(source.clone());
+
+        let string = scanner.scan_token();
+        assert_eq!(string.token_type as u8, TokenType::String as u8);
+        assert_eq!(
+            &source[string.start..(string.start + string.length)],
+            "\"héllo\""
+        );
+
+        assert_eq!(scanner.scan_token().token_type as u8, TokenType::Plus as u8);
+
+        // Non-ASCII letters aren't part of identifiers
+        assert_eq!(
+            scanner.scan_token().token_type as u8,
+            TokenType::Error as u8
+        );
+    }
 }
@@ -1,6 +1,9 @@
 use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
-use crate::chunk::Chunk;
+use crate::{
+    bytecode::{self, Reader},
+    chunk::Chunk,
+};
 
 #[derive(Debug, Clone)]
 pub struct Function {
@@ -8,6 +11,9 @@ pub struct Function {
     pub chunk: Chunk,
     pub name: Option<String>,
     pub upvalue_count: u8,
+    // A method declared without a parameter list, which runs as soon as
+    // it's accessed instead of waiting to be called
+    pub is_getter: bool,
 }
 
 impl Function {
@@ -17,8 +23,24 @@ impl Function {
             chunk: Chunk::new(),
             name: None,
             upvalue_count: 0,
+            is_getter: false,
         }
     }
+
+    #[allow(dead_code)]
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        bytecode::write_function(&mut output, self);
+        return output;
+    }
+
+    #[allow(dead_code)]
+    pub fn deserialize(bytes: &[u8]) -> Result<Function, String> {
+        let mut reader = Reader::new(bytes);
+        let function = reader.read_function()?;
+        reader.finish()?;
+        return Ok(function);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,12 +51,15 @@ pub struct NativeFunction {
 
 #[derive(Debug, Clone)]
 pub struct Closure {
-    pub function: Function,
+    pub function: Rc<Function>,
     pub upvalues: Vec<Upvalue>,
+    // Where `super` looks methods up: the superclass of the class a method
+    // was defined on. Closures made inside a method inherit it.
+    pub superclass: Option<Rc<Class>>,
 }
 
 impl Closure {
-    pub fn new(func: Function) -> Closure {
+    pub fn new(func: Rc<Function>) -> Closure {
         let mut upvalues = Vec::new();
         for _ in 0..func.upvalue_count {
             upvalues.push(Upvalue {
@@ -48,6 +73,7 @@ impl Closure {
         Closure {
             function: func,
             upvalues,
+            superclass: None,
         }
     }
 }
@@ -63,26 +89,186 @@ pub struct Upvalue {
 #[derive(Debug, Clone)]
 pub struct Class {
     pub name: String,
+    pub methods: HashMap<String, Closure>,
+    pub superclass: Option<Rc<Class>>,
+}
+
+impl Class {
+    pub fn new(name: String) -> Class {
+        Class {
+            name,
+            methods: HashMap::new(),
+            superclass: None,
+        }
+    }
+
+    // Whether this class is `other` or inherits from it somewhere up the chain.
+    // Classes are compared by identity, so two classes that happen to share
+    // a name are still different classes.
+    pub fn is_subclass_of(&self, other: &Class) -> bool {
+        let mut class = Some(self);
+        while let Some(current) = class {
+            if std::ptr::eq(current, other) {
+                return true;
+            }
+            class = current.superclass.as_deref();
+        }
+        return false;
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Instance {
-    pub class: Class,
+    pub class: Rc<Class>,
     pub fields: HashMap<String, Value>,
 }
 
+// A method looked up off of an instance, carrying the instance along so
+// `this` can be bound when it's eventually called
+#[derive(Debug, Clone)]
+pub struct BoundMethod {
+    pub receiver: Box<Value>,
+    pub method: Closure,
+}
+
 #[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Boolean(bool),
+    Int(i64),
     Number(f64),
-    String(String),
-    Function(Function),
+    String(Rc<str>),
+    Function(Rc<Function>),
     NativeFunction(NativeFunction),
     Closure(Closure),
     Upvalue(Upvalue),
-    Class(Class),
+    Class(Rc<Class>),
     Instance(Rc<RefCell<Instance>>),
+    BoundMethod(BoundMethod),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<HashMap<String, Value>>>),
+}
+
+impl Value {
+    // What `typeof` reports, and a friendlier way to describe a value in
+    // error messages than dumping it
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Int(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_)
+            | Value::NativeFunction(_)
+            | Value::Closure(_)
+            | Value::BoundMethod(_) => "function",
+            Value::Upvalue(_) => "upvalue",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    // The form a value takes when a Lox program prints it. `Display` and the
+    // debug formatter are for looking at the VM, not for program output.
+    pub fn to_lox_string(&self) -> String {
+        let mut instance = |instance: &Rc<RefCell<Instance>>| {
+            Some(format!("{} instance", instance.borrow().class.name))
+        };
+        return self
+            .write_lox_string(&mut instance, &mut Vec::new())
+            .unwrap();
+    }
+
+    // Same as `to_lox_string`, except instances anywhere inside the value
+    // are written by `instance`. That's how the VM runs `toString` methods,
+    // and a None from it is passed straight back.
+    pub fn to_lox_string_with(
+        &self,
+        instance: &mut dyn FnMut(&Rc<RefCell<Instance>>) -> Option<String>,
+    ) -> Option<String> {
+        return self.write_lox_string(instance, &mut Vec::new());
+    }
+
+    // `seen` holds the lists and maps currently being written, so one that
+    // contains itself prints as `[...]` or `{...}` instead of recursing
+    // forever. Their contents are copied out first, since a `toString`
+    // method could change them while they're being written.
+    fn write_lox_string(
+        &self,
+        instance: &mut dyn FnMut(&Rc<RefCell<Instance>>) -> Option<String>,
+        seen: &mut Vec<usize>,
+    ) -> Option<String> {
+        let output = match self {
+            Value::Nil => String::from("nil"),
+            Value::Boolean(b) => format!("{}", b),
+            Value::Int(n) => format!("{}", n),
+            Value::Number(n) => format!("{:?}", n),
+            Value::String(s) => s.to_string(),
+            Value::Function(func) => match &func.name {
+                Some(name) => format!("<fn {}>", name),
+                None => String::from("<script>"),
+            },
+            Value::NativeFunction(func) => format!("<native fn {}>", func.name),
+            Value::Closure(closure) => match &closure.function.name {
+                Some(name) => format!("<fn {}>", name),
+                None => String::from("<script>"),
+            },
+            Value::Upvalue(upvalue) => format!("{:?}", upvalue),
+            Value::Class(c) => c.name.clone(),
+            Value::Instance(i) => instance(i)?,
+            Value::BoundMethod(bound) => match &bound.method.function.name {
+                Some(name) => format!("<fn {}>", name),
+                None => String::from("<fn>"),
+            },
+            Value::List(list) => {
+                let pointer = Rc::as_ptr(list) as usize;
+                if seen.contains(&pointer) {
+                    return Some(String::from("[...]"));
+                }
+                seen.push(pointer);
+
+                let items = list.borrow().clone();
+                let mut parts = Vec::with_capacity(items.len());
+                for item in items {
+                    parts.push(item.write_lox_string(instance, seen)?);
+                }
+
+                seen.pop();
+                format!("[{}]", parts.join(", "))
+            }
+            // Keys are sorted so printing a map doesn't depend on hash order
+            Value::Map(map) => {
+                let pointer = Rc::as_ptr(map) as usize;
+                if seen.contains(&pointer) {
+                    return Some(String::from("{...}"));
+                }
+                seen.push(pointer);
+
+                let mut entries: Vec<(String, Value)> = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+                let mut parts = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    parts.push(format!(
+                        "{}: {}",
+                        key,
+                        value.write_lox_string(instance, seen)?
+                    ));
+                }
+
+                seen.pop();
+                format!("{{{}}}", parts.join(", "))
+            }
+        };
+
+        return Some(output);
+    }
 }
 
 impl fmt::Display for Value {
@@ -98,8 +284,11 @@ impl fmt::Display for Value {
                     write!(f, "BOOLEAN: false")
                 }
             }
+            Value::Int(n) => {
+                write!(f, "INT: {}", n)
+            }
             Value::Number(n) => {
-                write!(f, "NUMBER: {}", n)
+                write!(f, "NUMBER: {:?}", n)
             }
             Value::String(s) => {
                 write!(f, "STRING: {}", s)
@@ -132,6 +321,116 @@ impl fmt::Display for Value {
             Value::Instance(i) => {
                 write!(f, "{} instance", i.borrow().class.name)
             }
+            Value::BoundMethod(b) => match &b.method.function.name {
+                Some(name) => {
+                    write!(f, "<bound method {}>", name)
+                }
+                None => {
+                    write!(f, "<bound method>")
+                }
+            },
+            Value::List(list) => {
+                write!(f, "LIST: {} items", list.borrow().len())
+            }
+            Value::Map(map) => {
+                write!(f, "MAP: {} entries", map.borrow().len())
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named_function(name: &str) -> Rc<Function> {
+        let mut function = Function::new();
+        function.name = Some(String::from(name));
+        Rc::new(function)
+    }
+
+    #[test]
+    fn to_lox_string() {
+        assert_eq!(Value::Nil.to_lox_string(), "nil");
+        assert_eq!(Value::Boolean(true).to_lox_string(), "true");
+        assert_eq!(Value::Boolean(false).to_lox_string(), "false");
+        assert_eq!(Value::Int(3).to_lox_string(), "3");
+        assert_eq!(Value::Number(3.0).to_lox_string(), "3.0");
+        assert_eq!(Value::Number(0.5).to_lox_string(), "0.5");
+        assert_eq!(Value::String("foo".into()).to_lox_string(), "foo");
+
+        assert_eq!(
+            Value::Function(named_function("add")).to_lox_string(),
+            "<fn add>"
+        );
+        assert_eq!(
+            Value::Function(Rc::new(Function::new())).to_lox_string(),
+            "<script>"
+        );
+        assert_eq!(
+            Value::Closure(Closure::new(named_function("add"))).to_lox_string(),
+            "<fn add>"
+        );
+        assert_eq!(
+            Value::NativeFunction(NativeFunction {
+                name: String::from("clock"),
+                arity: 0,
+            })
+            .to_lox_string(),
+            "<native fn clock>"
+        );
+
+        let class = Rc::new(Class::new(String::from("Foo")));
+        assert_eq!(Value::Class(Rc::clone(&class)).to_lox_string(), "Foo");
+
+        let instance = Value::Instance(Rc::new(RefCell::new(Instance {
+            class,
+            fields: HashMap::new(),
+        })));
+        assert_eq!(instance.to_lox_string(), "Foo instance");
+        assert_eq!(
+            Value::BoundMethod(BoundMethod {
+                receiver: Box::new(instance),
+                method: Closure::new(named_function("bar")),
+            })
+            .to_lox_string(),
+            "<fn bar>"
+        );
+
+        let list = Value::List(Rc::new(RefCell::new(vec![
+            Value::Int(1),
+            Value::String("two".into()),
+            Value::Nil,
+        ])));
+        assert_eq!(list.to_lox_string(), "[1, two, nil]");
+
+        let mut map = HashMap::new();
+        map.insert(String::from("b"), Value::Boolean(true));
+        map.insert(String::from("a"), Value::Number(1.5));
+        assert_eq!(
+            Value::Map(Rc::new(RefCell::new(map))).to_lox_string(),
+            "{a: 1.5, b: true}"
+        );
+    }
+
+    #[test]
+    fn to_lox_string_cycles() {
+        let list = Rc::new(RefCell::new(vec![Value::Int(1)]));
+        list.borrow_mut().push(Value::List(list.clone()));
+        assert_eq!(Value::List(list.clone()).to_lox_string(), "[1, [...]]");
+
+        let map = Rc::new(RefCell::new(HashMap::new()));
+        map.borrow_mut()
+            .insert(String::from("self"), Value::Map(map.clone()));
+        assert_eq!(Value::Map(map.clone()).to_lox_string(), "{self: {...}}");
+
+        // The same list twice over isn't a cycle
+        let inner = Value::List(Rc::new(RefCell::new(vec![Value::Int(2)])));
+        let outer = Value::List(Rc::new(RefCell::new(vec![inner.clone(), inner])));
+        assert_eq!(outer.to_lox_string(), "[[2], [2]]");
+
+        // Break the cycles so the test doesn't leak
+        list.borrow_mut().clear();
+        map.borrow_mut().clear();
+    }
+}
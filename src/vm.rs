@@ -1,16 +1,22 @@
 use std::{
-    array,
     cell::RefCell,
     collections::HashMap,
+    error::Error,
+    fmt, fs,
+    io::{self, BufRead, Write},
     rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
     chunk::{Chunk, OpCode},
     compiler::{Compiler, FunctionType},
+    debug::{get_value_debug_string, write_debug::disassemble_instruction},
+    json,
+    math::limit::parse_polynomial,
     scanner::Scanner,
-    value::{Closure, Function, Instance, NativeFunction, Upvalue, Value},
+    value::{BoundMethod, Class, Closure, Function, Instance, NativeFunction, Upvalue, Value},
 };
 
 #[derive(Debug)]
@@ -20,11 +26,30 @@ pub enum InterpretResult {
     RuntimeError,
 }
 
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: usize,
+    // Where in the source the failing instruction came from, when known
+    pub span: Option<(usize, usize)>,
+    pub stack_trace: String,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Runtime error: {}", self.line, self.message)
+    }
+}
+
+impl Error for RuntimeError {}
+
 #[derive(Debug)]
 pub struct CallFrame {
     pub closure: Closure,
     ip: usize,
-    slot: usize, // <-- pointer into vm value stack
+    start_ip: usize, // <-- offset of the instruction currently being run
+    slot: usize,     // <-- pointer into vm value stack
 }
 
 pub trait ValueStack {
@@ -78,18 +103,66 @@ impl ValueStack for Vec<Value> {
     }
 }
 
-const MAX_FRAMES: usize = 64;
+// How deep calls can nest before we report a stack overflow, unless the VM
+// is built with a different limit
+const DEFAULT_MAX_FRAMES: usize = 64;
+
+// Source of the current time for the `clock` and `now` natives, as a
+// duration since the unix epoch. Tests swap this out to get deterministic
+// timings.
+pub type Clock = Box<dyn FnMut() -> Duration>;
+
+fn system_clock() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards.")
+}
+
+// Natives report their own runtime errors and return None when they fail
+type NativeFn<T> = fn(&mut VM<T>, Vec<Value>) -> Option<Value>;
 
 pub struct VM<T: ValueStack> {
     pub chunk: Chunk,
     pub value_stack: T,
 
     globals: HashMap<String, Value>,
+    // Native functions are looked up by name when they're called
+    natives: HashMap<String, NativeFn<T>>,
 
-    pub frames: [CallFrame; MAX_FRAMES],
+    // Frames are reused once they've been allocated, so this only grows
+    pub frames: Vec<CallFrame>,
     frame_count: usize,
+    max_frames: usize,
 
     open_upvalue_head: Option<Box<Upvalue>>,
+
+    // The error that stopped the last run, if it failed
+    last_error: Option<RuntimeError>,
+
+    clock: Clock,
+
+    // Everything the running program prints goes here, stdout by default
+    output: Box<dyn Write>,
+    // `read_line` reads from here, stdin by default
+    input: Box<dyn BufRead>,
+    // Compiler warnings go here rather than into the program's output,
+    // stderr by default
+    warning_output: Box<dyn Write>,
+    trace: bool,
+    flush_on_print: bool,
+
+    // Instructions executed by the latest run. Unlike wall clock timings
+    // this is the same on every machine, so it's a stable way to compare
+    // optimizations.
+    instruction_count: u64,
+    // Execution stops with a runtime error once instruction_count goes past
+    // this, so scripts from elsewhere can't hang whoever is running them
+    instruction_limit: Option<u64>,
+
+    // Instance pairs whose `equals` method is currently running
+    equals_in_progress: Vec<(*const RefCell<Instance>, *const RefCell<Instance>)>,
+    // Instances whose `toString` method is currently running
+    to_string_in_progress: Vec<*const RefCell<Instance>>,
 }
 
 impl<T: ValueStack> VM<T> {
@@ -99,52 +172,183 @@ impl<T: ValueStack> VM<T> {
             value_stack: Vec::new(),
 
             globals: HashMap::new(),
+            natives: HashMap::new(),
 
-            frames: array::from_fn(move |_| CallFrame {
-                closure: Closure::new(Function::new()),
-                ip: 0,
-                slot: 0,
-            }),
+            frames: Vec::new(),
             frame_count: 0,
+            max_frames: DEFAULT_MAX_FRAMES,
 
             open_upvalue_head: None,
+
+            last_error: None,
+
+            clock: Box::new(system_clock),
+
+            output: Box::new(io::stdout()),
+            input: Box::new(io::BufReader::new(io::stdin())),
+            warning_output: Box::new(io::stderr()),
+            trace: false,
+            flush_on_print: false,
+
+            instruction_count: 0,
+            instruction_limit: None,
+
+            equals_in_progress: Vec::new(),
+            to_string_in_progress: Vec::new(),
         };
 
-        vm.globals.insert(
-            String::from("clock"),
-            Value::NativeFunction(NativeFunction {
-                name: String::from("clock"),
-                arity: 0,
-            }),
-        );
-        vm.globals.insert(
-            String::from("limit"),
-            Value::NativeFunction(NativeFunction {
-                name: String::from("limit"),
-                arity: 1,
-            }),
-        );
+        vm.define_natives();
 
         return vm;
     }
 
     #[allow(dead_code)]
     pub fn new_with_value_stack(value_stack: T) -> VM<T> {
-        VM {
+        let mut vm = VM {
             chunk: Chunk::new(),
             value_stack,
 
             globals: HashMap::new(),
+            natives: HashMap::new(),
 
-            frames: array::from_fn(move |_| CallFrame {
-                closure: Closure::new(Function::new()),
-                ip: 0,
-                slot: 0,
-            }),
+            frames: Vec::new(),
             frame_count: 0,
+            max_frames: DEFAULT_MAX_FRAMES,
 
             open_upvalue_head: None,
-        }
+
+            last_error: None,
+
+            clock: Box::new(system_clock),
+
+            output: Box::new(io::stdout()),
+            input: Box::new(io::BufReader::new(io::stdin())),
+            warning_output: Box::new(io::stderr()),
+            trace: false,
+            flush_on_print: false,
+
+            instruction_count: 0,
+            instruction_limit: None,
+
+            equals_in_progress: Vec::new(),
+            to_string_in_progress: Vec::new(),
+        };
+
+        vm.define_natives();
+
+        vm
+    }
+
+    #[allow(dead_code)]
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_warning_output(&mut self, warning_output: Box<dyn Write>) {
+        self.warning_output = warning_output;
+    }
+
+    // Disassembles every instruction, along with the value stack, right
+    // before it's executed
+    #[allow(dead_code)]
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    // Flushes the output after every print so interactive programs don't
+    // leave anything sitting in a buffer. Batch runs can leave this off.
+    pub fn with_flush_on_print(mut self, flush_on_print: bool) -> Self {
+        self.flush_on_print = flush_on_print;
+        self
+    }
+
+    // Sets how deeply calls can nest before a "Stack overflow." error
+    #[allow(dead_code)]
+    pub fn with_max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    #[allow(dead_code)]
+    pub fn set_instruction_limit(&mut self, instruction_limit: Option<u64>) {
+        self.instruction_limit = instruction_limit;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    // Lets a host read back what a script computed once it has run
+    #[allow(dead_code)]
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        return self.globals.get(name).cloned();
+    }
+
+    // Lets a host hand a script its inputs before running it
+    #[allow(dead_code)]
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_owned(), value);
+    }
+
+    // Native functions are globals too, so they show up in here
+    #[allow(dead_code)]
+    pub fn globals_iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        return self.globals.iter();
+    }
+
+    fn define_native(&mut self, name: &str, arity: u8, native: NativeFn<T>) {
+        self.natives.insert(String::from(name), native);
+        self.globals.insert(
+            String::from(name),
+            Value::NativeFunction(NativeFunction {
+                name: String::from(name),
+                arity,
+            }),
+        );
+    }
+
+    fn define_natives(&mut self) {
+        self.define_native("clock", 0, VM::native_clock);
+        self.define_native("now", 0, VM::native_now);
+        self.define_native("limit", 2, VM::native_limit);
+        self.define_native("clone", 1, VM::native_clone);
+        self.define_native("sleep", 1, VM::native_sleep);
+        self.define_native("poly_eval", 2, VM::native_poly_eval);
+        self.define_native("json", 1, VM::native_json);
+        self.define_native("stringify", 1, VM::native_stringify);
+        self.define_native("from_json_file", 1, VM::native_from_json_file);
+        self.define_native("to_json_file", 2, VM::native_to_json_file);
+        self.define_native("str", 1, VM::native_str);
+        self.define_native("len", 1, VM::native_len);
+        self.define_native("substring", 3, VM::native_substring);
+        self.define_native("index_of", 2, VM::native_index_of);
+        self.define_native("to_upper", 1, VM::native_to_upper);
+        self.define_native("to_lower", 1, VM::native_to_lower);
+        self.define_native("read_line", 0, VM::native_read_line);
+        self.define_native("number", 1, VM::native_number);
+        self.define_native("assert", 2, VM::native_assert);
+        self.define_native("typeof", 1, VM::native_typeof);
+    }
+
+    // The opcodes collapsed by the peephole pass are a comparison followed
+    // by OP_NOT, so they run the comparison and then flip its result
+    fn negate_result(&mut self) {
+        let result = self.value_stack.pop().unwrap();
+        self.value_stack
+            .push(Value::Boolean(VM::<T>::is_falsey(result)));
     }
 
     fn is_falsey(value: Value) -> bool {
@@ -155,44 +359,180 @@ impl<T: ValueStack> VM<T> {
         }
     }
 
-    fn print_value(value: Value) {
+    // Bitwise operators only make sense on whole numbers, so anything with a
+    // fractional part (or outside of the i64 range) is rejected
+    fn as_integer(value: &Option<Value>) -> Option<i64> {
         match value {
-            Value::String(s) => {
-                for i in s.split("\\n") {
-                    println!("{}", i);
-                }
+            Some(Value::Int(n)) => Some(*n),
+            Some(Value::Number(n)) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => {
+                Some(*n as i64)
             }
-            Value::Number(n) => println!("{}", n),
-            Value::Boolean(b) => {
-                if b {
-                    println!("true");
-                } else {
-                    println!("false");
-                }
+            _ => None,
+        }
+    }
+
+    // Checks that `list[index]` refers to an element that exists, reporting
+    // a runtime error if it doesn't
+    fn list_index(
+        &mut self,
+        list: &Option<Value>,
+        index: &Option<Value>,
+    ) -> Option<(Rc<RefCell<Vec<Value>>>, usize)> {
+        let Some(Value::List(list)) = list else {
+            self.runtime_error(
+                format!("Can only index into lists and maps, got {:?}", list).as_str(),
+            );
+            return None;
+        };
+
+        let Some(Value::Int(idx)) = index else {
+            self.runtime_error(format!("List index must be an integer, got {:?}", index).as_str());
+            return None;
+        };
+
+        let length = list.borrow().len();
+        if *idx < 0 || *idx as usize >= length {
+            self.runtime_error(
+                format!(
+                    "List index {} is out of bounds for a list of length {}",
+                    idx, length
+                )
+                .as_str(),
+            );
+            return None;
+        }
+
+        Some((Rc::clone(list), *idx as usize))
+    }
+
+    fn map_key(&mut self, key: &Option<Value>) -> Option<String> {
+        match key {
+            Some(Value::String(key)) => Some(key.to_string()),
+            key => {
+                self.runtime_error(format!("Map keys must be strings, got {:?}", key).as_str());
+                None
             }
-            Value::Nil => println!("nil"),
-            Value::Function(func) => match func.name {
-                Some(name) => {
-                    println!("<fn {}>", name)
-                }
-                None => {
-                    println!("<script>")
-                }
-            },
-            Value::NativeFunction(_func) => {
-                println!("<native fn>");
+        }
+    }
+
+    // Numbers follow IEEE 754: NaN isn't equal to anything, itself included,
+    // and every ordering comparison with it is false, `<=` and `>=` too.
+    // Since `0 / 0` is a division by zero error, NaN can only come from
+    // natives like `number`.
+    fn numbers_equal(a: f64, b: f64) -> bool {
+        return a == b;
+    }
+
+    // Each comparison is done directly rather than as the negation of its
+    // opposite, since `!(a < b)` isn't `a >= b` when NaN is involved
+    fn compare<V: PartialOrd>(op: &OpCode, a: V, b: V) -> bool {
+        match op {
+            OpCode::Greater => return a > b,
+            OpCode::GreaterEqual => return a >= b,
+            OpCode::Less => return a < b,
+            OpCode::LessEqual => return a <= b,
+            _ => unreachable!("{} isn't a comparison", op),
+        }
+    }
+
+    // How many values an instruction takes off the top of the stack, or looks
+    // at there. Calls, lists and maps depend on their operand byte.
+    fn stack_inputs(op: &OpCode, operand: Option<u8>) -> usize {
+        let operand = operand.unwrap_or(0) as usize;
+
+        match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetGlobal
+            | OpCode::GetLocal
+            | OpCode::Jump
+            | OpCode::Loop
+            | OpCode::Closure
+            | OpCode::GetUpvalue
+            | OpCode::Class => return 0,
+            OpCode::Return
+            | OpCode::Negate
+            | OpCode::Not
+            | OpCode::Print
+            | OpCode::Println
+            | OpCode::Pop
+            | OpCode::Dup
+            | OpCode::Length
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::SetLocal
+            | OpCode::JumpIfFalse
+            | OpCode::SetUpvalue
+            | OpCode::CloseUpvalue
+            | OpCode::GetProperty
+            | OpCode::GetSuper => return 1,
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Power
+            | OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight
+            | OpCode::Index
+            | OpCode::SetProperty
+            | OpCode::Method
+            | OpCode::Inherit
+            | OpCode::IsInstance => return 2,
+            OpCode::SetIndex | OpCode::Rotate => return 3,
+            OpCode::Call => return operand + 1,
+            OpCode::BuildList => return operand,
+            OpCode::BuildMap => return operand * 2,
+        }
+    }
+
+    fn comparison_symbol(op: &OpCode) -> &'static str {
+        match op {
+            OpCode::Greater => return ">",
+            OpCode::GreaterEqual => return ">=",
+            OpCode::Less => return "<",
+            _ => return "<=",
+        }
+    }
+
+    fn as_float(value: &Value) -> Option<f64> {
+        match value {
+            Value::Int(n) => Some(*n as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // What a Lox program sees when it prints a value, adds it to a string or
+    // turns it into one. Unlike `Value::to_lox_string` this runs `toString`
+    // methods, including on instances inside lists and maps, so it gives
+    // back None when one of them hits a runtime error.
+    fn lox_string(&mut self, value: Value) -> Option<String> {
+        return value.to_lox_string_with(&mut |instance| match self
+            .instance_to_printable(Rc::clone(instance))?
+        {
+            Value::String(s) => Some(s.to_string()),
+            value => Some(value.to_lox_string()),
+        });
+    }
+
+    fn print_value(&mut self, value: Value) -> bool {
+        match self.lox_string(value) {
+            Some(s) => {
+                write!(self.output, "{}", s).expect("Couldn't write to output");
+                return true;
             }
-            Value::Closure(closure) => match &closure.function.name {
-                Some(name) => {
-                    println!("<closure {}>", name);
-                }
-                None => {
-                    println!("<closure>");
-                }
-            },
-            Value::Upvalue(upvalue) => println!("{:?}", upvalue),
-            Value::Class(c) => println!("{}", c.name),
-            Value::Instance(i) => println!("{} instance", i.borrow().class.name),
+            None => return false,
         }
     }
 
@@ -202,7 +542,7 @@ impl<T: ValueStack> VM<T> {
 
         for frame_idx in 0..self.frame_count {
             let frame = &self.frames[frame_idx];
-            let line = frame.closure.function.chunk.lines[frame.ip];
+            let line = frame.closure.function.chunk.line_at(frame.start_ip);
 
             match &frame.closure.function.name {
                 Some(s) => {
@@ -223,9 +563,70 @@ impl<T: ValueStack> VM<T> {
         return output;
     }
 
-    fn runtime_error(&self, message: &str) {
+    // A run that ends in an error leaves its values and frames behind, so
+    // each run starts from a clean slate. Globals are kept on purpose.
+    fn reset(&mut self) {
+        while self.value_stack.pop().is_some() {}
+        self.frame_count = 0;
+        self.open_upvalue_head = None;
+        self.last_error = None;
+        self.instruction_count = 0;
+        self.equals_in_progress.clear();
+        self.to_string_in_progress.clear();
+    }
+
+    fn runtime_error(&mut self, message: &str) {
         let stack_trace = self.stack_trace();
-        println!("{}\n{}", stack_trace, message);
+        writeln!(self.output, "{}\n{}", stack_trace, message).expect("Couldn't write to output");
+
+        self.last_error = Some(RuntimeError {
+            message: String::from(message),
+            line: self.current_line(),
+            span: self.current_span(),
+            stack_trace,
+        });
+    }
+
+    // Line of the instruction that's running now
+    fn current_line(&self) -> usize {
+        match self.frame_count {
+            0 => return 0,
+            n => {
+                let frame = &self.frames[n - 1];
+                return frame.closure.function.chunk.line_at(frame.start_ip);
+            }
+        }
+    }
+
+    fn current_span(&self) -> Option<(usize, usize)> {
+        match self.frame_count {
+            0 => return None,
+            n => {
+                let frame = &self.frames[n - 1];
+                return frame.closure.function.chunk.span_at(frame.start_ip);
+            }
+        }
+    }
+
+    // For hosts that need to know why `interpret` returned a runtime error
+    #[allow(dead_code)]
+    pub fn last_error(&self) -> Option<&RuntimeError> {
+        return self.last_error.as_ref();
+    }
+
+    // Prints the value stack followed by the instruction that's about to run
+    fn trace_instruction(&mut self) {
+        let mut stack = String::from("          ");
+        for idx in 0..self.value_stack.size() {
+            let value = self.value_stack.get_value_at_idx(idx);
+            stack.push_str(format!("[ {} ]", get_value_debug_string(&value)).as_str());
+        }
+
+        let frame = &self.frames[self.frame_count - 1];
+        let (instruction, _) = disassemble_instruction(&frame.closure.function.chunk, frame.ip);
+
+        write!(self.output, "{}\n{:0>4} {}", stack, frame.ip, instruction)
+            .expect("Couldn't write to output");
     }
 
     fn call(&mut self, closure: Closure, arg_count: u8) -> bool {
@@ -240,22 +641,38 @@ impl<T: ValueStack> VM<T> {
             return false;
         }
 
-        if self.frame_count == MAX_FRAMES {
+        if self.frame_count == self.max_frames {
             self.runtime_error("Stack overflow.");
             return false;
         }
 
-        self.frames[self.frame_count].closure = closure;
-        self.frames[self.frame_count].ip = 0;
-        self.frames[self.frame_count].slot = self.value_stack.size() - (arg_count as usize) - 1;
+        let frame = CallFrame {
+            closure,
+            ip: 0,
+            start_ip: 0,
+            slot: self.value_stack.size() - (arg_count as usize) - 1,
+        };
+
+        if self.frame_count == self.frames.len() {
+            self.frames.push(frame);
+        } else {
+            self.frames[self.frame_count] = frame;
+        }
 
         self.frame_count += 1;
 
         return true;
     }
 
-    #[allow(unreachable_code)]
     fn call_native(&mut self, func: NativeFunction, arg_count: u8) -> bool {
+        let native = match self.natives.get(&func.name) {
+            Some(native) => *native,
+            None => {
+                self.runtime_error(format!("No native function named '{}'", func.name).as_str());
+                return false;
+            }
+        };
+
         if arg_count != func.arity {
             self.runtime_error(
                 format!("Expected {} arguments but got {}", func.arity, arg_count).as_str(),
@@ -263,157 +680,585 @@ impl<T: ValueStack> VM<T> {
             return false;
         }
 
-        if self.frame_count == MAX_FRAMES {
+        if self.frame_count == self.max_frames {
             self.runtime_error("Stack overflow.");
             return false;
         }
 
-        match func.name.as_str() {
-            "clock" => {
-                let start = SystemTime::now();
-                let since_the_epoch = start
-                    .duration_since(UNIX_EPOCH)
-                    .expect("time went backwards.");
-
-                self.value_stack.pop(); // pop off the function itself
-                self.value_stack
-                    .push(Value::Number(since_the_epoch.as_millis() as f64));
+        let mut args = Vec::with_capacity(arg_count as usize);
+        for _ in 0..arg_count {
+            args.push(self.value_stack.pop().unwrap());
+        }
+        args.reverse();
+        self.value_stack.pop(); // pop off the function itself
 
+        match native(self, args) {
+            Some(result) => {
+                self.value_stack.push(result);
                 return true;
             }
-            "limit" => {
-                todo!("Clean this up to do more interesting things");
-                let maybe_number = self.value_stack.pop();
-                self.value_stack.pop(); // pop off the function itself
-
-                match maybe_number {
-                    Some(Value::Closure(f)) => {
-                        self.value_stack.push(Value::String(format!("{:?}", f)));
-                        return true;
-                    }
-                    Some(Value::Number(number)) => {
-                        let f = |x: f64| -> f64 {
-                            if x < 0.0 {
-                                return -1.0;
-                            } else {
-                                return 1.0;
-                            }
-                        };
-
-                        let delta = 1.0 / 2.0_f64.powf(32.0);
-
-                        let limit_from_left = f(number - delta);
-                        let limit_from_right = f(number + delta);
+            None => return false,
+        }
+    }
 
-                        let tol = 10.0_f64.powi(-6);
+    fn native_clock(&mut self, _args: Vec<Value>) -> Option<Value> {
+        let since_the_epoch = (self.clock)();
+        return Some(Value::Int(since_the_epoch.as_millis() as i64));
+    }
 
-                        if (limit_from_left - limit_from_right).abs() < tol {
-                            self.value_stack
-                                .push(Value::Number((limit_from_left + limit_from_right) / 2.0));
-                        } else {
-                            self.value_stack.push(Value::Nil);
-                        }
+    // Seconds as a float, for timings finer than `clock`'s milliseconds
+    fn native_now(&mut self, _args: Vec<Value>) -> Option<Value> {
+        let since_the_epoch = (self.clock)();
+        return Some(Value::Number(since_the_epoch.as_secs_f64()));
+    }
 
-                        return true;
-                    }
-                    _ => {
-                        self.runtime_error(
-                            format!("Can't call <limit> with input {:?}", maybe_number).as_str(),
-                        );
-                        return false;
-                    }
-                }
+    fn native_sleep(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        match args.pop() {
+            // Duration panics on infinity, so that's rejected like a negative
+            Some(Value::Number(ms)) if ms >= 0.0 && ms.is_finite() => {
+                thread::sleep(Duration::from_secs_f64(ms / 1000.0));
             }
-            s => {
-                self.runtime_error(format!("No native function named '{}'", s).as_str());
-                return false;
+            Some(Value::Int(ms)) if ms >= 0 => {
+                thread::sleep(Duration::from_millis(ms as u64));
+            }
+            value => {
+                self.runtime_error(
+                    format!(
+                        "<sleep> expects a finite, non-negative number of milliseconds, got {:?}",
+                        value
+                    )
+                    .as_str(),
+                );
+                return None;
             }
         }
+
+        return Some(Value::Nil);
     }
 
-    fn call_value(&mut self, callee: Value, arg_count: u8) -> bool {
-        match callee {
-            Value::Class(class) => {
-                self.value_stack.set_value_at_idx(
-                    self.value_stack.size() - arg_count as usize - 1,
-                    Value::Instance(Rc::new(RefCell::new(Instance {
-                        class: class.clone(),
-                        fields: HashMap::new(),
-                    }))),
-                );
-                return true;
+    fn native_clone(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        match args.pop() {
+            // Instances are shared references, so cloning one means
+            // building a new instance with its own copy of the fields.
+            // This is a shallow copy; instances stored in the fields
+            // are still shared.
+            Some(Value::Instance(instance)) => {
+                let copy = instance.borrow().clone();
+                return Some(Value::Instance(Rc::new(RefCell::new(copy))));
             }
-            Value::Closure(closure) => {
-                return self.call(closure, arg_count);
+            Some(Value::List(list)) => {
+                let copy = list.borrow().clone();
+                return Some(Value::List(Rc::new(RefCell::new(copy))));
             }
-            Value::NativeFunction(func) => {
-                return self.call_native(func, arg_count);
+            Some(Value::Map(map)) => {
+                let copy = map.borrow().clone();
+                return Some(Value::Map(Rc::new(RefCell::new(copy))));
             }
-            v => {
-                let v = v.to_owned();
-                self.runtime_error(format!("Can't call value {:?}", v).as_str());
-                return false;
+            // Everything else already behaves like a value
+            Some(value) => return Some(value),
+            None => {
+                self.runtime_error("Can't call <clone> without a value.");
+                return None;
             }
         }
     }
 
-    fn capture_upvalue(&mut self, index: usize) -> Upvalue {
-        let mut previous_upvalue: Option<Box<Upvalue>> = None;
-        let mut upvalue = self.open_upvalue_head.clone();
-
-        while upvalue.clone().is_some()
-            && upvalue.clone().unwrap().location > self.frames[self.frame_count - 1].slot + index
-        {
-            previous_upvalue = upvalue.clone();
-            upvalue = upvalue.unwrap().next;
+    fn native_json(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        match args.pop() {
+            Some(Value::String(source)) => match json::parse(&source) {
+                Ok(value) => return Some(value),
+                Err(message) => {
+                    self.runtime_error(format!("<json> {}", message).as_str());
+                    return None;
+                }
+            },
+            value => {
+                self.runtime_error(format!("<json> expects a string, got {:?}", value).as_str());
+                return None;
+            }
         }
+    }
 
-        // if the upvalue is the one we're looking for
-        if upvalue.is_some()
-            && upvalue.clone().unwrap().location == self.frames[self.frame_count - 1].slot + index
-        {
-            return *(upvalue.clone()).unwrap();
+    fn native_stringify(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        match json::stringify(&args.pop().unwrap_or(Value::Nil)) {
+            Ok(s) => return Some(Value::String(s.into())),
+            Err(message) => {
+                self.runtime_error(format!("<stringify> {}", message).as_str());
+                return None;
+            }
         }
+    }
 
-        let mut new_upvalue = Upvalue {
-            location: self.frames[self.frame_count - 1].slot + index,
-            index,
-            next: None,
-            closed: None,
+    fn native_from_json_file(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let path = self.string_argument("from_json_file", args.pop())?;
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                self.runtime_error(
+                    format!("<from_json_file> Couldn't read {}: {}", path, e).as_str(),
+                );
+                return None;
+            }
         };
-        new_upvalue.next = upvalue;
 
-        if previous_upvalue.is_none() {
-            self.open_upvalue_head = Some(Box::new(new_upvalue.clone()));
-        } else {
-            previous_upvalue.unwrap().next = Some(Box::new(new_upvalue.clone()));
+        match json::parse(&source) {
+            Ok(value) => return Some(value),
+            Err(message) => {
+                self.runtime_error(format!("<from_json_file> {}", message).as_str());
+                return None;
+            }
         }
-
-        return new_upvalue;
     }
 
-    fn close_upvalues(&mut self, closure: &mut Closure) {
-        let slot = self.frames[self.frame_count - 1].slot;
+    fn native_to_json_file(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let path = self.string_argument("to_json_file", args.pop())?;
 
-        for idx in 0..closure.upvalues.len() {
-            match closure.upvalues[idx].closed {
-                None => {
-                    if closure.upvalues[idx].location > slot {
-                        closure.upvalues[idx].closed = Some(Box::new(
-                            self.value_stack
-                                .get_value_at_idx(closure.upvalues[idx].location)
-                                .clone(),
-                        ));
-                    }
-                }
-                _ => {}
+        let contents = match json::stringify(&args.pop().unwrap_or(Value::Nil)) {
+            Ok(contents) => contents,
+            Err(message) => {
+                self.runtime_error(format!("<to_json_file> {}", message).as_str());
+                return None;
             }
+        };
+
+        if let Err(e) = fs::write(&path, contents) {
+            self.runtime_error(format!("<to_json_file> Couldn't write {}: {}", path, e).as_str());
+            return None;
         }
+
+        return Some(Value::Nil);
     }
 
-    #[allow(dead_code)]
-    fn debug_open_upvalue_list(&mut self) {
-        let mut head = self.open_upvalue_head.clone();
+    // The same text `print` would show for the value
+    fn native_str(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let value = args.pop().unwrap_or(Value::Nil);
+        return Some(Value::String(self.lox_string(value)?.into()));
+    }
+
+    // Strings are measured in characters rather than bytes
+    fn native_len(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let length = match args.pop() {
+            Some(Value::String(s)) => s.chars().count(),
+            Some(Value::List(list)) => list.borrow().len(),
+            Some(Value::Map(map)) => map.borrow().len(),
+            value => {
+                self.runtime_error(
+                    format!("<len> expects a string, list or map, got {:?}", value).as_str(),
+                );
+                return None;
+            }
+        };
+
+        return Some(Value::Int(length as i64));
+    }
+
+    fn string_argument(&mut self, native: &str, value: Option<Value>) -> Option<String> {
+        match value {
+            Some(Value::String(s)) => return Some(s.to_string()),
+            value => {
+                self.runtime_error(
+                    format!("<{}> expects a string, got {:?}", native, value).as_str(),
+                );
+                return None;
+            }
+        }
+    }
+
+    // `start` and `end` count characters, and `end` is exclusive
+    fn native_substring(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let end = args.pop();
+        let start = args.pop();
+        let s = self.string_argument("substring", args.pop())?;
+
+        let length = s.chars().count() as i64;
+        let (start, end) = match (VM::<T>::as_integer(&start), VM::<T>::as_integer(&end)) {
+            (Some(start), Some(end)) if 0 <= start && start <= end && end <= length => {
+                (start as usize, end as usize)
+            }
+            (Some(start), Some(end)) => {
+                self.runtime_error(
+                    format!(
+                        "<substring> range {}..{} is out of bounds for a string of length {}",
+                        start, end, length
+                    )
+                    .as_str(),
+                );
+                return None;
+            }
+            _ => {
+                self.runtime_error(
+                    format!(
+                        "<substring> expects integer bounds, got {:?} and {:?}",
+                        start, end
+                    )
+                    .as_str(),
+                );
+                return None;
+            }
+        };
+
+        let substring: String = s.chars().skip(start).take(end - start).collect();
+        return Some(Value::String(substring.into()));
+    }
+
+    // The character index of the first match, or -1 if there isn't one
+    fn native_index_of(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let needle = self.string_argument("index_of", args.pop())?;
+        let s = self.string_argument("index_of", args.pop())?;
+
+        let index = match s.find(&needle) {
+            Some(byte_index) => s[..byte_index].chars().count() as i64,
+            None => -1,
+        };
+
+        return Some(Value::Int(index));
+    }
+
+    fn native_to_upper(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let s = self.string_argument("to_upper", args.pop())?;
+        return Some(Value::String(s.to_uppercase().into()));
+    }
+
+    fn native_to_lower(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let s = self.string_argument("to_lower", args.pop())?;
+        return Some(Value::String(s.to_lowercase().into()));
+    }
+
+    // Returns the next line of input without its line ending, or nil once
+    // the input runs out
+    fn native_read_line(&mut self, _args: Vec<Value>) -> Option<Value> {
+        // Anything printed as a prompt should show up before we block
+        self.output.flush().expect("Couldn't flush output");
+
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) => return Some(Value::Nil),
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                return Some(Value::String(line.into()));
+            }
+            Err(error) => {
+                self.runtime_error(format!("<read_line> couldn't read input: {}", error).as_str());
+                return None;
+            }
+        }
+    }
+
+    // Bad input gives back nil instead of an error so scripts can check
+    // what the user typed
+    fn native_number(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let s = self.string_argument("number", args.pop())?;
+
+        match s.trim().parse::<f64>() {
+            Ok(n) => return Some(Value::Number(n)),
+            Err(_) => return Some(Value::Nil),
+        }
+    }
+
+    fn native_assert(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let message = args.pop().unwrap_or(Value::Nil);
+        let condition = args.pop().unwrap_or(Value::Nil);
+
+        if VM::<T>::is_falsey(condition) {
+            self.runtime_error(format!("Assertion failed: {}", message.to_lox_string()).as_str());
+            return None;
+        }
+
+        return Some(Value::Nil);
+    }
+
+    fn native_typeof(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let value = args.pop().unwrap_or(Value::Nil);
+        return Some(Value::String(value.type_name().into()));
+    }
+
+    fn native_poly_eval(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let x = args.pop();
+        let expression = args.pop();
+
+        let x = match x.as_ref().and_then(VM::<T>::as_float) {
+            Some(x) => x,
+            None => {
+                self.runtime_error(
+                    format!("<poly_eval> expects a number to evaluate at, got {:?}", x).as_str(),
+                );
+                return None;
+            }
+        };
+
+        match expression {
+            Some(Value::String(expression)) => match parse_polynomial(expression.to_string()) {
+                Ok(polynomial) => return Some(Value::Number(polynomial.compute(x))),
+                Err(message) => {
+                    self.runtime_error(format!("<poly_eval> {}", message).as_str());
+                    return None;
+                }
+            },
+            value => {
+                self.runtime_error(
+                    format!("<poly_eval> expects a polynomial string, got {:?}", value).as_str(),
+                );
+                return None;
+            }
+        }
+    }
+
+    fn native_limit(&mut self, mut args: Vec<Value>) -> Option<Value> {
+        let point = args.pop();
+        let function = args.pop();
+
+        let point = match point.as_ref().and_then(VM::<T>::as_float) {
+            Some(point) => point,
+            None => {
+                self.runtime_error(
+                    format!("<limit> expects a number to approach, got {:?}", point).as_str(),
+                );
+                return None;
+            }
+        };
+
+        let delta = 1.0 / 2.0_f64.powf(32.0);
+
+        let (limit_from_left, limit_from_right) = match function {
+            Some(Value::String(expression)) => match parse_polynomial(expression.to_string()) {
+                Ok(polynomial) => (
+                    polynomial.compute(point - delta),
+                    polynomial.compute(point + delta),
+                ),
+                Err(message) => {
+                    self.runtime_error(format!("<limit> {}", message).as_str());
+                    return None;
+                }
+            },
+            Some(Value::Closure(closure)) => {
+                let mut sample = |x: f64| -> Option<f64> {
+                    // A plain function sits in its own slot 0
+                    let result = self.call_method(
+                        Value::Closure(closure.clone()),
+                        closure.clone(),
+                        vec![Value::Number(x)],
+                    )?;
+
+                    match VM::<T>::as_float(&result) {
+                        Some(y) => Some(y),
+                        None => {
+                            self.runtime_error(
+                                format!(
+                                    "<limit> expects a number from the function, got {:?}",
+                                    result
+                                )
+                                .as_str(),
+                            );
+                            None
+                        }
+                    }
+                };
+
+                (sample(point - delta)?, sample(point + delta)?)
+            }
+            value => {
+                self.runtime_error(
+                    format!(
+                        "<limit> expects a function or a polynomial string, got {:?}",
+                        value
+                    )
+                    .as_str(),
+                );
+                return None;
+            }
+        };
+
+        // The limit only exists when both sides agree
+        let tol = 10.0_f64.powi(-6);
+
+        if (limit_from_left - limit_from_right).abs() < tol {
+            return Some(Value::Number((limit_from_left + limit_from_right) / 2.0));
+        } else {
+            return Some(Value::Nil);
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: u8) -> bool {
+        match callee {
+            Value::Class(class) => {
+                let initializer = class.methods.get("init").cloned();
+
+                self.value_stack.set_value_at_idx(
+                    self.value_stack.size() - arg_count as usize - 1,
+                    Value::Instance(Rc::new(RefCell::new(Instance {
+                        class: Rc::clone(&class),
+                        fields: HashMap::new(),
+                    }))),
+                );
+
+                match initializer {
+                    Some(initializer) => return self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        self.runtime_error(
+                            format!("Expected 0 arguments but got {}", arg_count).as_str(),
+                        );
+                        return false;
+                    }
+                    None => return true,
+                }
+            }
+            Value::BoundMethod(bound) => {
+                self.value_stack.set_value_at_idx(
+                    self.value_stack.size() - arg_count as usize - 1,
+                    *bound.receiver,
+                );
+                return self.call(bound.method, arg_count);
+            }
+            Value::Closure(closure) => {
+                return self.call(closure, arg_count);
+            }
+            Value::NativeFunction(func) => {
+                return self.call_native(func, arg_count);
+            }
+            v => {
+                let v = v.to_owned();
+                self.runtime_error(format!("Can't call value {:?}", v).as_str());
+                return false;
+            }
+        }
+    }
+
+    // Runs a method to completion from inside of an opcode and hands back
+    // whatever it returned, or None if it hit a runtime error
+    fn call_method(&mut self, receiver: Value, method: Closure, args: Vec<Value>) -> Option<Value> {
+        let arg_count = args.len() as u8;
+        let base_frame = self.frame_count;
+
+        self.value_stack.push(receiver);
+        for arg in args {
+            self.value_stack.push(arg);
+        }
+
+        if !self.call(method, arg_count) {
+            return None;
+        }
+
+        match self.run(base_frame) {
+            InterpretResult::Ok => self.value_stack.pop(),
+            _ => None,
+        }
+    }
+
+    // Classes can opt into value equality by defining `equals(other)`,
+    // otherwise two instances are only equal if they're the same instance
+    fn instances_equal(
+        &mut self,
+        a: Rc<RefCell<Instance>>,
+        b: Rc<RefCell<Instance>>,
+    ) -> Option<bool> {
+        let equals = a.borrow().class.methods.get("equals").cloned();
+        let pair = (Rc::as_ptr(&a), Rc::as_ptr(&b));
+
+        match equals {
+            // An `equals` that ends up comparing the same pair again would
+            // never finish, so that comparison falls back to identity
+            Some(method) if !self.equals_in_progress.contains(&pair) => {
+                self.equals_in_progress.push(pair);
+                let result = self.call_method(Value::Instance(a), method, vec![Value::Instance(b)]);
+                self.equals_in_progress.pop();
+
+                result.map(|value| !VM::<T>::is_falsey(value))
+            }
+            _ => Some(Rc::ptr_eq(&a, &b)),
+        }
+    }
+
+    // Instances print whatever their `toString` method returns if their class
+    // defines one. An instance printing itself from inside of its own
+    // `toString` gets the default representation instead.
+    fn instance_to_printable(&mut self, instance: Rc<RefCell<Instance>>) -> Option<Value> {
+        let to_string = instance.borrow().class.methods.get("toString").cloned();
+        let pointer = Rc::as_ptr(&instance);
+
+        match to_string {
+            Some(method) if !self.to_string_in_progress.contains(&pointer) => {
+                self.to_string_in_progress.push(pointer);
+                let result = self.call_method(Value::Instance(instance), method, Vec::new());
+                self.to_string_in_progress.pop();
+
+                match result {
+                    Some(Value::String(s)) => Some(Value::String(s)),
+                    Some(value) => {
+                        self.runtime_error(
+                            format!("toString must return a string, got {:?}", value).as_str(),
+                        );
+                        None
+                    }
+                    None => None,
+                }
+            }
+            _ => Some(Value::Instance(instance)),
+        }
+    }
+
+    fn capture_upvalue(&mut self, index: usize) -> Upvalue {
+        let mut previous_upvalue: Option<Box<Upvalue>> = None;
+        let mut upvalue = self.open_upvalue_head.clone();
+
+        while upvalue.clone().is_some()
+            && upvalue.clone().unwrap().location > self.frames[self.frame_count - 1].slot + index
+        {
+            previous_upvalue = upvalue.clone();
+            upvalue = upvalue.unwrap().next;
+        }
+
+        // if the upvalue is the one we're looking for
+        if upvalue.is_some()
+            && upvalue.clone().unwrap().location == self.frames[self.frame_count - 1].slot + index
+        {
+            return *(upvalue.clone()).unwrap();
+        }
+
+        let mut new_upvalue = Upvalue {
+            location: self.frames[self.frame_count - 1].slot + index,
+            index,
+            next: None,
+            closed: None,
+        };
+        new_upvalue.next = upvalue;
+
+        if previous_upvalue.is_none() {
+            self.open_upvalue_head = Some(Box::new(new_upvalue.clone()));
+        } else {
+            previous_upvalue.unwrap().next = Some(Box::new(new_upvalue.clone()));
+        }
+
+        return new_upvalue;
+    }
+
+    fn close_upvalues(&mut self, closure: &mut Closure) {
+        let slot = self.frames[self.frame_count - 1].slot;
+
+        for idx in 0..closure.upvalues.len() {
+            match closure.upvalues[idx].closed {
+                None => {
+                    if closure.upvalues[idx].location > slot {
+                        closure.upvalues[idx].closed = Some(Box::new(
+                            self.value_stack
+                                .get_value_at_idx(closure.upvalues[idx].location)
+                                .clone(),
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    fn debug_open_upvalue_list(&mut self) {
+        let mut head = self.open_upvalue_head.clone();
 
         println!("======== START UPVALUE LIST ========\n");
 
@@ -425,7 +1270,9 @@ impl<T: ValueStack> VM<T> {
         println!("\n======== END UPVALUE LIST ========");
     }
 
-    fn run(&mut self) -> InterpretResult {
+    // Runs until the frame count drops back down to `base_frame`, so a
+    // method called from inside of an opcode can run to completion
+    fn run(&mut self, base_frame: usize) -> InterpretResult {
         macro_rules! frame {
             () => {
                 &mut self.frames[self.frame_count - 1]
@@ -468,29 +1315,74 @@ impl<T: ValueStack> VM<T> {
             }};
         }
 
+        // The compiler never emits bytecode that reaches outside the stack,
+        // but a corrupt `.loxc` file could, and indexing past the end would
+        // panic instead of reporting an error
+        macro_rules! check_slot {
+            ($slot:expr) => {
+                if $slot >= self.value_stack.size() {
+                    self.runtime_error(
+                        format!(
+                            "VM stack corruption: slot {} is out of range for a stack of {}.",
+                            $slot,
+                            self.value_stack.size()
+                        )
+                        .as_str(),
+                    );
+                    return InterpretResult::RuntimeError;
+                }
+            };
+        }
+
+        // Two ints stay an int, anything mixed with a float becomes a float
+        macro_rules! arithmetic {
+            ($a:expr, $b:expr, $op:tt, $checked:ident) => {
+                match ($a, $b) {
+                    (Value::Int(num1), Value::Int(num2)) => match num1.$checked(num2) {
+                        Some(n) => self.value_stack.push(Value::Int(n)),
+                        None if num2 == 0 => {
+                            self.runtime_error("Division by zero.");
+                            return InterpretResult::RuntimeError;
+                        }
+                        None => {
+                            self.runtime_error(
+                                format!(
+                                    "Integer overflow in {} {} {}",
+                                    num1,
+                                    stringify!($op),
+                                    num2
+                                )
+                                .as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                    },
+                    (a, b) => {
+                        let num1 = VM::<T>::as_float(&a).unwrap();
+                        let num2 = VM::<T>::as_float(&b).unwrap();
+                        self.value_stack.push(Value::Number(num1 $op num2));
+                    }
+                }
+            };
+        }
+
         macro_rules! binary_op {
-            ($op:tt) => {
+            ($op:tt, $checked:ident) => {
                 let b = self.value_stack.pop();
                 let a = self.value_stack.pop();
 
                 match b {
-                    Some(Value::Number(num2)) => match a {
-                        Some(Value::Number(num1)) => {
-                            self.value_stack.push(Value::Number(num1 $op num2));
+                    Some(num2) if VM::<T>::as_float(&num2).is_some() => match a {
+                        Some(num1) if VM::<T>::as_float(&num1).is_some() => {
+                            arithmetic!(num1, num2, $op, $checked);
                         }
                         _ => {
-                            let ip = frame!().ip;
-                            let line = frame!().closure.function.chunk.lines[ip];
-
-                            println!("[Error on line {}]\nPerforming binary operation because LHS isn't a number. LHS = {:?}", line, a);
+                            self.runtime_error(format!("Performing binary operation because LHS isn't a number. LHS = {:?}", a).as_str());
                             return InterpretResult::RuntimeError;
                         }
                     },
                     _ => {
-                        let ip = frame!().ip;
-                        let line = frame!().closure.function.chunk.lines[ip];
-
-                        println!("[Error on line {}]\nPerforming binary operation because RHS isn't a number. RHS = {:?}", line, b);
+                        self.runtime_error(format!("Performing binary operation because RHS isn't a number. RHS = {:?}", b).as_str());
                         return InterpretResult::RuntimeError;
                     }
                 }
@@ -498,7 +1390,52 @@ impl<T: ValueStack> VM<T> {
         }
 
         loop {
-            let instruction = get_instruction!().unwrap();
+            if self.trace {
+                self.trace_instruction();
+            }
+
+            // Operands advance the ip, so errors report the line of the
+            // instruction's first byte instead
+            frame!().start_ip = frame!().ip;
+            let instruction = match get_instruction!() {
+                Some(instruction) => instruction,
+                None => {
+                    let start_ip = frame!().start_ip;
+                    let byte = frame!().closure.function.chunk.code[start_ip];
+                    self.runtime_error(format!("Unknown opcode {}.", byte).as_str());
+                    return InterpretResult::RuntimeError;
+                }
+            };
+            self.instruction_count += 1;
+
+            // Everything this instruction pops or peeks at has to be there
+            // already, so none of the arms below can run off the stack
+            let operand = {
+                let ip = frame!().ip;
+                frame!().closure.function.chunk.code.get(ip).copied()
+            };
+            let inputs = VM::<T>::stack_inputs(&instruction, operand);
+            if inputs > self.value_stack.size() {
+                self.runtime_error(
+                    format!(
+                        "VM stack corruption: {:?} can't run on a stack of {}, it needs {}.",
+                        instruction,
+                        self.value_stack.size(),
+                        inputs
+                    )
+                    .as_str(),
+                );
+                return InterpretResult::RuntimeError;
+            }
+
+            if let Some(limit) = self.instruction_limit {
+                if self.instruction_count > limit {
+                    self.runtime_error(
+                        format!("Execution limit exceeded ({} instructions).", limit).as_str(),
+                    );
+                    return InterpretResult::RuntimeError;
+                }
+            }
 
             match instruction {
                 OpCode::Return => {
@@ -523,57 +1460,56 @@ impl<T: ValueStack> VM<T> {
                         self.value_stack.pop();
                     }
                     self.value_stack.push(result);
+
+                    if self.frame_count == base_frame {
+                        return InterpretResult::Ok;
+                    }
                 }
                 OpCode::Constant => {
                     let constant = read_constant!();
                     self.value_stack.push(constant.clone());
                 }
+                // Adding to a string concatenates the other operand's printed
+                // form, whatever its type, so `"n=" + nil` is `"n=nil"`
                 OpCode::Add => {
                     let b = self.value_stack.pop();
                     let a = self.value_stack.pop();
-
-                    match b {
-                        Some(Value::Number(num2)) => match a {
-                            Some(Value::Number(num1)) => {
-                                self.value_stack.push(Value::Number(num1 + num2));
-                            }
-                            Some(Value::String(s1)) => self
+                    let (Some(a), Some(b)) = (a, b) else {
+                        self.runtime_error("VM stack corruption: addition is missing an operand.");
+                        return InterpretResult::RuntimeError;
+                    };
+
+                    match (a, b) {
+                        (num1, num2)
+                            if VM::<T>::as_float(&num1).is_some()
+                                && VM::<T>::as_float(&num2).is_some() =>
+                        {
+                            arithmetic!(num1, num2, +, checked_add);
+                        }
+                        (Value::String(s1), Value::String(s2)) => {
+                            self.value_stack
+                                .push(Value::String(format!("{}{}", s1, s2).into()));
+                        }
+                        (Value::String(s1), value) => match self.lox_string(value) {
+                            Some(s2) => self
                                 .value_stack
-                                .push(Value::String(format!("{}{}", s1, num2))),
-                            value => {
-                                let value = value.to_owned();
-                                self.runtime_error(
-                                    format!(
-                                        "LHS of addition can't be added to a number: {:?}",
-                                        value
-                                    )
-                                    .as_str(),
-                                );
-                                return InterpretResult::RuntimeError;
-                            }
+                                .push(Value::String(format!("{}{}", s1, s2).into())),
+                            None => return InterpretResult::RuntimeError,
                         },
-                        Some(Value::String(s2)) => match a {
-                            Some(Value::String(s1)) => {
-                                self.value_stack
-                                    .push(Value::String(format!("{}{}", s1, s2)));
-                            }
-                            Some(Value::Number(n)) => {
-                                self.value_stack.push(Value::String(format!("{}{}", n, s2)));
-                            }
-                            value => {
-                                let value = value.to_owned();
-                                self.runtime_error(
-                                    format!(
-                                        "LHS of addition can't be added to a string: {:?}",
-                                        value
-                                    )
-                                    .as_str(),
-                                );
-                                return InterpretResult::RuntimeError;
-                            }
+                        (value, Value::String(s2)) => match self.lox_string(value) {
+                            Some(s1) => self
+                                .value_stack
+                                .push(Value::String(format!("{}{}", s1, s2).into())),
+                            None => return InterpretResult::RuntimeError,
                         },
-                        value => {
-                            let value = value.to_owned();
+                        (value, num2) if VM::<T>::as_float(&num2).is_some() => {
+                            self.runtime_error(
+                                format!("LHS of addition can't be added to a number: {:?}", value)
+                                    .as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                        (_, value) => {
                             self.runtime_error(
                                 format!("RHS of addition is an invalid addend: {:?}", value)
                                     .as_str(),
@@ -583,13 +1519,100 @@ impl<T: ValueStack> VM<T> {
                     }
                 }
                 OpCode::Subtract => {
-                    binary_op!(-);
+                    binary_op!(-, checked_sub);
                 }
                 OpCode::Multiply => {
-                    binary_op!(*);
+                    binary_op!(*, checked_mul);
                 }
                 OpCode::Divide => {
-                    binary_op!(/);
+                    // Floats would quietly give back inf or NaN here, but
+                    // dividing by zero is an error no matter the number type
+                    let divisor = VM::<T>::as_float(&self.value_stack.peek(0));
+                    if divisor == Some(0.0) {
+                        self.runtime_error("Division by zero.");
+                        return InterpretResult::RuntimeError;
+                    }
+
+                    binary_op!(/, checked_div);
+                }
+                OpCode::Power => {
+                    let b = self.value_stack.pop();
+                    let a = self.value_stack.pop();
+
+                    let (Some(num1), Some(num2)) = (
+                        a.as_ref().and_then(VM::<T>::as_float),
+                        b.as_ref().and_then(VM::<T>::as_float),
+                    ) else {
+                        self.runtime_error(
+                            format!("Operands of ** must be numbers, got {:?} and {:?}", a, b)
+                                .as_str(),
+                        );
+                        return InterpretResult::RuntimeError;
+                    };
+
+                    // Ints with a non-negative exponent stay ints, everything
+                    // else is done in floating point
+                    match (a, b) {
+                        (Some(Value::Int(base)), Some(Value::Int(exponent))) if exponent >= 0 => {
+                            match u32::try_from(exponent)
+                                .ok()
+                                .and_then(|exponent| base.checked_pow(exponent))
+                            {
+                                Some(n) => self.value_stack.push(Value::Int(n)),
+                                None => {
+                                    self.runtime_error(
+                                        format!("Integer overflow in {} ** {}", base, exponent)
+                                            .as_str(),
+                                    );
+                                    return InterpretResult::RuntimeError;
+                                }
+                            }
+                        }
+                        _ => self.value_stack.push(Value::Number(num1.powf(num2))),
+                    }
+                }
+                op @ (OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight) => {
+                    let b = self.value_stack.pop();
+                    let a = self.value_stack.pop();
+
+                    let (Some(num1), Some(num2)) =
+                        (VM::<T>::as_integer(&a), VM::<T>::as_integer(&b))
+                    else {
+                        self.runtime_error(
+                            format!(
+                                "Operands of {} must be integers, got {:?} and {:?}",
+                                op, a, b
+                            )
+                            .as_str(),
+                        );
+                        return InterpretResult::RuntimeError;
+                    };
+
+                    let result = match op {
+                        OpCode::BitAnd => Some(num1 & num2),
+                        OpCode::BitOr => Some(num1 | num2),
+                        OpCode::BitXor => Some(num1 ^ num2),
+                        OpCode::ShiftLeft => u32::try_from(num2)
+                            .ok()
+                            .and_then(|shift| num1.checked_shl(shift)),
+                        _ => u32::try_from(num2)
+                            .ok()
+                            .and_then(|shift| num1.checked_shr(shift)),
+                    };
+
+                    match result {
+                        Some(n) => self.value_stack.push(Value::Int(n)),
+                        None => {
+                            self.runtime_error(
+                                format!("Shift amount {} is out of range.", num2).as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
                 }
                 OpCode::True => {
                     self.value_stack.push(Value::Boolean(true));
@@ -618,6 +1641,13 @@ impl<T: ValueStack> VM<T> {
 
                     match v {
                         Some(Value::Number(n)) => self.value_stack.push(Value::Number(-n)),
+                        Some(Value::Int(n)) => match n.checked_neg() {
+                            Some(n) => self.value_stack.push(Value::Int(n)),
+                            None => {
+                                self.runtime_error(format!("Integer overflow in -{}", n).as_str());
+                                return InterpretResult::RuntimeError;
+                            }
+                        },
                         value => {
                             let value = value.to_owned();
                             self.runtime_error(
@@ -627,15 +1657,37 @@ impl<T: ValueStack> VM<T> {
                         }
                     }
                 }
-                OpCode::Equal => {
+                op @ (OpCode::Equal | OpCode::NotEqual) => {
                     let b = self.value_stack.pop();
                     let a = self.value_stack.pop();
 
                     match b {
-                        Some(Value::Number(num2)) => match a {
-                            Some(Value::Number(num1)) => {
+                        Some(Value::Instance(instance2)) => match a {
+                            Some(Value::Instance(instance1)) => {
+                                match self.instances_equal(instance1, instance2) {
+                                    Some(equal) => self.value_stack.push(Value::Boolean(equal)),
+                                    None => return InterpretResult::RuntimeError,
+                                }
+                            }
+                            None => return InterpretResult::RuntimeError,
+                            _ => self.value_stack.push(Value::Boolean(false)),
+                        },
+                        Some(Value::Int(num2)) => match a {
+                            Some(Value::Int(num1)) => {
                                 self.value_stack.push(Value::Boolean(num1 == num2))
                             }
+                            Some(Value::Number(num1)) => self
+                                .value_stack
+                                .push(Value::Boolean(VM::<T>::numbers_equal(num1, num2 as f64))),
+                            None => return InterpretResult::RuntimeError,
+                            _ => self.value_stack.push(Value::Boolean(false)),
+                        },
+                        Some(Value::Number(num2)) => match a {
+                            Some(num1) if VM::<T>::as_float(&num1).is_some() => {
+                                let num1 = VM::<T>::as_float(&num1).unwrap();
+                                self.value_stack
+                                    .push(Value::Boolean(VM::<T>::numbers_equal(num1, num2)))
+                            }
                             None => return InterpretResult::RuntimeError,
                             _ => self.value_stack.push(Value::Boolean(false)),
                         },
@@ -660,107 +1712,234 @@ impl<T: ValueStack> VM<T> {
                         None => return InterpretResult::RuntimeError,
                         _ => self.value_stack.push(Value::Boolean(false)),
                     }
+
+                    if let OpCode::NotEqual = op {
+                        self.negate_result();
+                    }
                 }
-                OpCode::Greater => {
+                op
+                @ (OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual) => {
                     let b = self.value_stack.pop();
                     let a = self.value_stack.pop();
 
-                    match b {
-                        Some(Value::Number(num2)) => match a {
-                            Some(Value::Number(num1)) => {
-                                self.value_stack.push(Value::Boolean(num1 > num2))
-                            }
-                            value => {
-                                let value = value.to_owned();
-                                self.runtime_error(
-                                    format!("Can't perform > operation on value {:?}", value)
-                                        .as_str(),
-                                );
-                                return InterpretResult::RuntimeError;
-                            }
-                        },
-                        value => {
-                            let value = value.to_owned();
+                    let result = match (a, b) {
+                        (Some(Value::Int(num1)), Some(Value::Int(num2))) => {
+                            VM::<T>::compare(&op, num1, num2)
+                        }
+                        (Some(a), Some(b))
+                            if VM::<T>::as_float(&a).is_some()
+                                && VM::<T>::as_float(&b).is_some() =>
+                        {
+                            VM::<T>::compare(
+                                &op,
+                                VM::<T>::as_float(&a).unwrap(),
+                                VM::<T>::as_float(&b).unwrap(),
+                            )
+                        }
+                        (Some(Value::String(str1)), Some(Value::String(str2))) => {
+                            VM::<T>::compare(&op, str1, str2)
+                        }
+                        (Some(Value::String(_)), Some(number))
+                        | (Some(number), Some(Value::String(_)))
+                            if VM::<T>::as_float(&number).is_some() =>
+                        {
+                            self.runtime_error("Can't compare a number with a string. Operands must both be numbers or both be strings.");
+                            return InterpretResult::RuntimeError;
+                        }
+                        (a, b) => {
+                            // Report whichever side isn't a number
+                            let value = match &b {
+                                Some(b) if VM::<T>::as_float(b).is_some() => a,
+                                _ => b,
+                            };
                             self.runtime_error(
-                                format!("Can't perform > operation on value {:?}", value).as_str(),
+                                format!(
+                                    "Can't perform {} operation on value {:?}",
+                                    VM::<T>::comparison_symbol(&op),
+                                    value
+                                )
+                                .as_str(),
                             );
                             return InterpretResult::RuntimeError;
                         }
+                    };
+
+                    self.value_stack.push(Value::Boolean(result));
+                }
+                op @ (OpCode::Print | OpCode::Println) => {
+                    let printed = match self.value_stack.pop() {
+                        Some(Value::Upvalue(upvalue)) => match upvalue.closed {
+                            None => {
+                                /*
+                                 * The issue is that in the C version of the code, the value of
+                                 * an upvalue is accessed directly by just dereferencing the location
+                                 * property, which points directly to the place in memory where
+                                 * the value itself lives.
+                                 *
+                                 * In the Rust paradigm here, that's all fucked because the location
+                                 * is meant to point to an index in the value stack. When a value gets
+                                 * closed, the value stack by definition no longer has the value in it.
+                                 *
+                                 * So, any pointer to an index in the value stack means nothing. How in
+                                 * the world could I fix this?
+                                 */
+                                check_slot!(upvalue.location);
+                                self.print_value(
+                                    self.value_stack.get_value_at_idx(upvalue.location),
+                                )
+                            }
+                            Some(closed) => {
+                                println!("here?");
+                                self.print_value(*closed)
+                            }
+                        },
+                        Some(v) => self.print_value(v),
+                        None => false,
+                    };
+                    if !printed {
+                        return InterpretResult::RuntimeError;
+                    }
+
+                    if let OpCode::Println = op {
+                        writeln!(self.output).expect("Couldn't write to output");
+                    }
+
+                    if self.flush_on_print {
+                        self.output.flush().expect("Couldn't flush output");
                     }
                 }
-                OpCode::Less => {
-                    let b = self.value_stack.pop();
-                    let a = self.value_stack.pop();
+                OpCode::Pop => {
+                    self.value_stack.pop();
+                }
+                OpCode::Dup => {
+                    self.value_stack.push(self.value_stack.peek(0));
+                }
+                OpCode::BuildList => {
+                    let item_count = read_byte!() as usize;
 
-                    match b {
-                        Some(Value::Number(num2)) => match a {
-                            Some(Value::Number(num1)) => {
-                                self.value_stack.push(Value::Boolean(num1 < num2))
+                    let mut items = Vec::with_capacity(item_count);
+                    for _ in 0..item_count {
+                        items.push(self.value_stack.pop().unwrap());
+                    }
+                    items.reverse();
+
+                    self.value_stack
+                        .push(Value::List(Rc::new(RefCell::new(items))));
+                }
+                OpCode::BuildMap => {
+                    let entry_count = read_byte!() as usize;
+
+                    let mut entries = Vec::with_capacity(entry_count);
+                    for _ in 0..entry_count {
+                        let value = self.value_stack.pop().unwrap();
+                        let key = self.value_stack.pop().unwrap();
+                        entries.push((key, value));
+                    }
+
+                    // Entries come off the stack backwards, so reversing them
+                    // means a repeated key keeps its last value
+                    let mut map = HashMap::with_capacity(entry_count);
+                    for (key, value) in entries.into_iter().rev() {
+                        match key {
+                            Value::String(key) => {
+                                map.insert(key.to_string(), value);
                             }
-                            value => {
-                                let value = value.to_owned();
+                            key => {
                                 self.runtime_error(
-                                    format!("Can't perform < operation on value {:?}", value)
-                                        .as_str(),
+                                    format!("Map keys must be strings, got {:?}", key).as_str(),
                                 );
                                 return InterpretResult::RuntimeError;
                             }
-                        },
+                        }
+                    }
+
+                    self.value_stack
+                        .push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                OpCode::Index => {
+                    let index = self.value_stack.pop();
+                    let list = self.value_stack.pop();
+
+                    // Reading a key that isn't in a map gives back nil
+                    if let Some(Value::Map(map)) = &list {
+                        match self.map_key(&index) {
+                            Some(key) => {
+                                let value = map.borrow().get(&key).cloned().unwrap_or(Value::Nil);
+                                self.value_stack.push(value);
+                            }
+                            None => return InterpretResult::RuntimeError,
+                        }
+                        continue;
+                    }
+
+                    match self.list_index(&list, &index) {
+                        Some((list, idx)) => {
+                            let value = list.borrow()[idx].clone();
+                            self.value_stack.push(value);
+                        }
+                        None => return InterpretResult::RuntimeError,
+                    }
+                }
+                OpCode::Length => {
+                    let length = match self.value_stack.pop() {
+                        Some(Value::List(list)) => list.borrow().len(),
                         value => {
-                            let value = value.to_owned();
                             self.runtime_error(
-                                format!("Can't perform < operation on value {:?}", value).as_str(),
+                                format!("Can only loop over lists, got {:?}", value).as_str(),
                             );
                             return InterpretResult::RuntimeError;
                         }
-                    }
+                    };
+
+                    self.value_stack.push(Value::Int(length as i64));
                 }
-                OpCode::Print => match self.value_stack.pop() {
-                    Some(Value::Upvalue(upvalue)) => match upvalue.closed {
-                        None => {
-                            /*
-                             * The issue is that in the C version of the code, the value of
-                             * an upvalue is accessed directly by just dereferencing the location
-                             * property, which points directly to the place in memory where
-                             * the value itself lives.
-                             *
-                             * In the Rust paradigm here, that's all fucked because the location
-                             * is meant to point to an index in the value stack. When a value gets
-                             * closed, the value stack by definition no longer has the value in it.
-                             *
-                             * So, any pointer to an index in the value stack means nothing. How in
-                             * the world could I fix this?
-                             */
-                            VM::<T>::print_value(
-                                self.value_stack.get_value_at_idx(upvalue.location),
-                            );
+                OpCode::SetIndex => {
+                    let value = self.value_stack.pop().unwrap();
+                    let index = self.value_stack.pop();
+                    let list = self.value_stack.pop();
+
+                    if let Some(Value::Map(map)) = &list {
+                        match self.map_key(&index) {
+                            Some(key) => {
+                                map.borrow_mut().insert(key, value.clone());
+                                self.value_stack.push(value);
+                            }
+                            None => return InterpretResult::RuntimeError,
                         }
-                        Some(closed) => {
-                            println!("here?");
-                            VM::<T>::print_value(*closed);
+                        continue;
+                    }
+
+                    match self.list_index(&list, &index) {
+                        Some((list, idx)) => {
+                            list.borrow_mut()[idx] = value.clone();
+                            self.value_stack.push(value);
                         }
-                    },
-                    Some(v) => VM::<T>::print_value(v),
-                    _ => return InterpretResult::RuntimeError,
-                },
-                OpCode::Pop => {
-                    self.value_stack.pop();
+                        None => return InterpretResult::RuntimeError,
+                    }
+                }
+                OpCode::Rotate => {
+                    // Moves the top value underneath the two below it
+                    let c = self.value_stack.pop().unwrap();
+                    let b = self.value_stack.pop().unwrap();
+                    let a = self.value_stack.pop().unwrap();
+
+                    self.value_stack.push(c);
+                    self.value_stack.push(a);
+                    self.value_stack.push(b);
                 }
                 OpCode::DefineGlobal => {
                     let name = read_constant!();
 
+                    // The value is popped and moved straight into the globals
+                    // rather than cloned off the top of the stack
                     match name {
                         Value::String(s) => {
-                            let value = self.value_stack.last_value().unwrap();
-
-                            self.globals.insert(s.to_owned(), value);
-                            self.value_stack.pop();
+                            let value = self.value_stack.pop().unwrap();
+                            self.globals.insert(s.to_string(), value);
                         }
                         Value::Class(c) => {
-                            let value = self.value_stack.last_value().unwrap();
-
+                            let value = self.value_stack.pop().unwrap();
                             self.globals.insert(c.name.to_owned(), value);
-                            self.value_stack.pop();
                         }
                         value => {
                             let value = value.to_owned();
@@ -777,7 +1956,7 @@ impl<T: ValueStack> VM<T> {
 
                     match name {
                         Value::String(s) => {
-                            let optional_value = self.globals.get(s);
+                            let optional_value = self.globals.get(&**s);
                             match optional_value {
                                 Some(value) => {
                                     self.value_stack.push(value.to_owned());
@@ -806,15 +1985,21 @@ impl<T: ValueStack> VM<T> {
 
                     match name {
                         Value::String(s) => {
-                            if !self.globals.contains_key(s) {
-                                let s = s.to_owned();
-                                self.runtime_error(
-                                    format!("Global var '{}' does not exist.", s).as_str(),
-                                );
-                                return InterpretResult::RuntimeError;
+                            // Assignment is an expression so the value stays on the
+                            // stack, but the existing entry is overwritten in place
+                            // instead of allocating a new key for it
+                            match self.globals.get_mut(&**s) {
+                                Some(global) => {
+                                    *global = self.value_stack.last_value().unwrap();
+                                }
+                                None => {
+                                    let s = s.to_owned();
+                                    self.runtime_error(
+                                        format!("Global var '{}' does not exist.", s).as_str(),
+                                    );
+                                    return InterpretResult::RuntimeError;
+                                }
                             }
-                            let value = self.value_stack.last_value().unwrap();
-                            self.globals.insert(s.to_owned(), value);
                         }
                         value => {
                             let value = value.to_owned();
@@ -826,14 +2011,16 @@ impl<T: ValueStack> VM<T> {
                     }
                 }
                 OpCode::GetLocal => {
-                    let slot = read_byte!() + frame!().slot as u8;
+                    let slot = read_byte!() as usize + frame!().slot;
+                    check_slot!(slot);
                     self.value_stack
-                        .push(self.value_stack.get_value_at_idx(slot as usize));
+                        .push(self.value_stack.get_value_at_idx(slot));
                 }
                 OpCode::SetLocal => {
-                    let slot = read_byte!() + frame!().slot as u8;
+                    let slot = read_byte!() as usize + frame!().slot;
+                    check_slot!(slot);
                     let top_value = self.value_stack.peek(0);
-                    self.value_stack.set_value_at_idx(slot as usize, top_value);
+                    self.value_stack.set_value_at_idx(slot, top_value);
                 }
                 OpCode::JumpIfFalse => {
                     let offset = read_short!();
@@ -862,8 +2049,10 @@ impl<T: ValueStack> VM<T> {
                     let value = read_constant!();
 
                     match value {
+                        // Every closure over the same function shares its chunk
                         Value::Function(func) => {
-                            let mut closure = Closure::new(func.to_owned());
+                            let mut closure = Closure::new(Rc::clone(func));
+                            closure.superclass = frame!().closure.superclass.clone();
 
                             for idx in 0..closure.upvalues.len() {
                                 let is_local = read_byte!();
@@ -872,10 +2061,13 @@ impl<T: ValueStack> VM<T> {
                                 // If is_local == 1, then the index value points to a local in the enclosing scope
                                 // else, it points to an upvalue in the enclosing scope
                                 if is_local == 1 {
+                                    let slot = frame!().slot + index;
+                                    check_slot!(slot);
                                     closure.upvalues[idx] = self.capture_upvalue(index);
                                 } else {
                                     if index >= frame!().closure.upvalues.len() {
                                         self.runtime_error("error creating higher upvalue");
+                                        return InterpretResult::RuntimeError;
                                     }
                                     closure.upvalues[idx] =
                                         frame!().closure.upvalues[index].clone();
@@ -922,6 +2114,7 @@ impl<T: ValueStack> VM<T> {
                         }
                         None => {
                             let location = frame!().closure.upvalues[slot as usize].location;
+                            check_slot!(location);
                             self.value_stack
                                 .set_value_at_idx(location, value_on_top_of_stack);
                         }
@@ -932,9 +2125,93 @@ impl<T: ValueStack> VM<T> {
                     // self.close_upvalues(self.value_stack.size() - 1);
                     // self.value_stack.pop();
                 }
-                OpCode::Class => {
-                    let value = read_constant!();
-                    self.value_stack.push(value.clone());
+                // Every time a declaration runs it makes a new class, even
+                // though they all start out as the same constant
+                OpCode::Class => match read_constant!().clone() {
+                    Value::Class(class) => {
+                        self.value_stack
+                            .push(Value::Class(Rc::new(Class::clone(&class))));
+                    }
+                    value => self.value_stack.push(value),
+                },
+                OpCode::Inherit => {
+                    let superclass = self.value_stack.pop();
+
+                    // Inheriting happens before any of the subclass's own
+                    // methods are attached, so those override these. Nothing
+                    // else has the new class yet, so `make_mut` never copies it.
+                    match (superclass, self.value_stack.pop()) {
+                        (Some(Value::Class(superclass)), Some(Value::Class(mut class))) => {
+                            let class_mut = Rc::make_mut(&mut class);
+                            class_mut.methods = superclass.methods.clone();
+                            class_mut.superclass = Some(superclass);
+                            self.value_stack.push(Value::Class(class));
+                        }
+                        (superclass, _) => {
+                            self.runtime_error(
+                                format!(
+                                    "Superclass must be a class, got {}",
+                                    superclass.as_ref().map_or("nothing", Value::type_name)
+                                )
+                                .as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
+                }
+                OpCode::IsInstance => {
+                    let class = self.value_stack.pop();
+                    let value = self.value_stack.pop();
+
+                    match (value, class) {
+                        (Some(Value::Instance(instance)), Some(Value::Class(class))) => {
+                            let is_instance = instance.borrow().class.is_subclass_of(&class);
+                            self.value_stack.push(Value::Boolean(is_instance));
+                        }
+                        (_, Some(Value::Class(_))) => {
+                            self.value_stack.push(Value::Boolean(false));
+                        }
+                        (_, class) => {
+                            self.runtime_error(
+                                format!(
+                                    "Right operand of 'is' must be a class, got {}",
+                                    class.as_ref().map_or("nothing", Value::type_name)
+                                )
+                                .as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
+                }
+                OpCode::Method => {
+                    let name = read_constant!().clone();
+                    let method = self.value_stack.pop();
+
+                    // The class is still sitting on the stack from OP_CLASS, so
+                    // it's updated in place with each method as it comes in
+                    match (name, method, self.value_stack.pop()) {
+                        (
+                            Value::String(name),
+                            Some(Value::Closure(mut method)),
+                            Some(Value::Class(mut class)),
+                        ) => {
+                            method.superclass = class.superclass.clone();
+                            Rc::make_mut(&mut class)
+                                .methods
+                                .insert(name.to_string(), method);
+                            self.value_stack.push(Value::Class(class));
+                        }
+                        (name, method, class) => {
+                            self.runtime_error(
+                                format!(
+                                    "Can't define method {:?} as {:?} on {:?}",
+                                    name, method, class
+                                )
+                                .as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
                 }
                 OpCode::GetProperty => {
                     let instance = self.value_stack.peek(0);
@@ -946,19 +2223,48 @@ impl<T: ValueStack> VM<T> {
                                 let owned_instance = Rc::clone(&instance);
                                 let borrowed_instance = owned_instance.borrow();
                                 let value_of_property =
-                                    borrowed_instance.fields.get(&property_name);
+                                    borrowed_instance.fields.get(&*property_name);
 
                                 match value_of_property {
                                     Some(value) => {
                                         self.value_stack.pop();
                                         self.value_stack.push(value.clone());
                                     }
-                                    None => {
-                                        self.runtime_error(
-                                            format!("Undefined property '{}'.", property_name)
-                                                .as_str(),
-                                        );
-                                    }
+                                    // Fields shadow methods, so the class is only
+                                    // checked once there's no field by that name
+                                    None => match borrowed_instance
+                                        .class
+                                        .methods
+                                        .get(&*property_name)
+                                    {
+                                        // The instance is already where a
+                                        // call expects its receiver
+                                        Some(method) if method.function.is_getter => {
+                                            let method = method.clone();
+                                            drop(borrowed_instance);
+                                            if !self.call(method, 0) {
+                                                return InterpretResult::RuntimeError;
+                                            }
+                                        }
+                                        Some(method) => {
+                                            let bound_method = Value::BoundMethod(BoundMethod {
+                                                receiver: Box::new(Value::Instance(Rc::clone(
+                                                    &instance,
+                                                ))),
+                                                method: method.clone(),
+                                            });
+
+                                            self.value_stack.pop();
+                                            self.value_stack.push(bound_method);
+                                        }
+                                        None => {
+                                            self.runtime_error(
+                                                format!("Undefined property '{}'.", property_name)
+                                                    .as_str(),
+                                            );
+                                            return InterpretResult::RuntimeError;
+                                        }
+                                    },
                                 }
                             }
                             _ => {
@@ -974,6 +2280,41 @@ impl<T: ValueStack> VM<T> {
                         }
                     }
                 }
+                // `super.name` looks the method up on the superclass of the
+                // class the running method was defined on, and binds it to
+                // the `this` underneath it on the stack
+                OpCode::GetSuper => {
+                    let name = read_constant!().clone();
+                    let method = match (&name, &frame!().closure.superclass) {
+                        (Value::String(name), Some(superclass)) => {
+                            superclass.methods.get(&**name).cloned()
+                        }
+                        _ => None,
+                    };
+
+                    match method {
+                        // `this` is already where a call expects its receiver
+                        Some(method) if method.function.is_getter => {
+                            if !self.call(method, 0) {
+                                return InterpretResult::RuntimeError;
+                            }
+                        }
+                        Some(method) => {
+                            let receiver = self.value_stack.pop().unwrap();
+                            self.value_stack.push(Value::BoundMethod(BoundMethod {
+                                receiver: Box::new(receiver),
+                                method,
+                            }));
+                        }
+                        None => {
+                            self.runtime_error(
+                                format!("Undefined superclass method '{}'.", name.to_lox_string())
+                                    .as_str(),
+                            );
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
+                }
                 OpCode::SetProperty => {
                     let instance = self.value_stack.peek(1);
                     let value_to_set_as = self.value_stack.peek(0);
@@ -986,7 +2327,7 @@ impl<T: ValueStack> VM<T> {
                                 Value::String(property_name) => {
                                     new_instance
                                         .fields
-                                        .insert(property_name.clone(), value_to_set_as);
+                                        .insert(property_name.to_string(), value_to_set_as);
                                 }
                                 _ => {
                                     self.runtime_error(
@@ -1014,18 +2355,33 @@ impl<T: ValueStack> VM<T> {
         let scanner = Scanner::new(source);
         let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
 
-        let compile_result = compiler.compile(None);
-        match compile_result {
-            None => return InterpretResult::CompileError,
-            Some(func) => {
-                let closure = Closure::new(func.to_owned());
+        let compile_result = compiler.compile(None).map(|func| func.to_owned());
+
+        for warning in compiler.warnings() {
+            writeln!(self.warning_output, "{}", warning).expect("Couldn't write warning");
+        }
 
-                self.value_stack.push(Value::Closure(closure.clone()));
-                self.call(closure.to_owned(), 0);
+        match compile_result {
+            None => {
+                for error in compiler.errors() {
+                    writeln!(self.output, "{}", error).expect("Couldn't write to output");
+                }
+                return InterpretResult::CompileError;
             }
+            Some(func) => return self.interpret_function(func),
         }
+    }
 
-        return self.run();
+    // Runs an already compiled script, e.g. one loaded from a `.loxc` file
+    pub fn interpret_function(&mut self, function: Function) -> InterpretResult {
+        self.reset();
+
+        let closure = Closure::new(Rc::new(function));
+
+        self.value_stack.push(Value::Closure(closure.clone()));
+        self.call(closure, 0);
+
+        return self.run(0);
     }
 }
 
@@ -1034,6 +2390,7 @@ mod tests {
     use std::vec;
 
     use super::*;
+    use crate::alloc_counter::{alloc_count, reset_alloc_count};
 
     struct TestValueStack<'a> {
         all_values: &'a mut Vec<Value>,
@@ -1107,12 +2464,12 @@ mod tests {
         );
 
         match last_value {
-            Some(Value::Number(n)) => {
-                if n != 3.0 {
-                    panic!("Expected 3.0, got {}", n);
+            Some(Value::Int(n)) => {
+                if n != 3 {
+                    panic!("Expected 3, got {}", n);
                 }
             }
-            _ => panic!("Expected 3.0, got {:?}", last_value),
+            _ => panic!("Expected 3, got {:?}", last_value),
         }
     }
 
@@ -1170,11 +2527,1902 @@ mod tests {
         );
         match last_value {
             Some(Value::String(s)) => {
-                if !s.eq("one two three") {
+                if &*s != "one two three" {
                     panic!("Expected 'one two three', got {:?}", s);
                 }
             }
             _ => panic!("Expected 'one two three', got {:?}", last_value),
         }
     }
+
+    #[test]
+    fn clone_instance() {
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from(
+                "class Point {}
+                var a = Point();
+                a.x = 1;
+                var b = clone(a);
+                b.x = 2;
+                a.x;",
+            ),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Int(n)) => {
+                if n != 1 {
+                    panic!("Expected 1, got {}", n);
+                }
+            }
+            _ => panic!("Expected 1, got {:?}", last_value),
+        }
+
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from(
+                "class Point {}
+                var a = Point();
+                a.x = 1;
+                var b = clone(a);
+                b.x = 2;
+                b.x;",
+            ),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Int(n)) => {
+                if n != 2 {
+                    panic!("Expected 2, got {}", n);
+                }
+            }
+            _ => panic!("Expected 2, got {:?}", last_value),
+        }
+    }
+
+    #[test]
+    fn clone_primitive() {
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from("clone(\"abc\");"),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::String(s)) => {
+                if &*s != "abc" {
+                    panic!("Expected 'abc', got {:?}", s);
+                }
+            }
+            _ => panic!("Expected 'abc', got {:?}", last_value),
+        }
+    }
+
+    #[test]
+    fn sleep() {
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("sleep(0);"));
+        assert!(matches!(result, InterpretResult::Ok));
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("sleep(-1);"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("sleep(\"soon\");"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("sleep(number(\"inf\"));"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn sleep_blocks() {
+        let start = std::time::Instant::now();
+        let (result, output) = run_and_capture("println sleep(1);");
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "nil\n");
+        assert!(start.elapsed() >= Duration::from_millis(1));
+    }
+
+    #[test]
+    fn injected_clock() {
+        let mut all_values = Vec::new();
+        let mut vm = VM::new_with_value_stack(TestValueStack::new(&mut all_values));
+
+        // Every reading is 250ms after the previous one
+        let mut now = Duration::from_millis(1000);
+        vm.set_clock(Box::new(move || {
+            now += Duration::from_millis(250);
+            now
+        }));
+
+        vm.interpret(String::from(
+            "var start = clock();
+            sleep(0);
+            clock() - start;",
+        ));
+
+        vm.value_stack.all_values.pop();
+        let last_value = vm.value_stack.all_values.pop();
+        match last_value {
+            Some(Value::Int(n)) => {
+                if n != 250 {
+                    panic!("Expected 250, got {}", n);
+                }
+            }
+            _ => panic!("Expected 250, got {:?}", last_value),
+        }
+    }
+
+    #[test]
+    fn now_native() {
+        let (result, output) = run_and_capture(
+            "var first = now();
+            var second = now();
+            println first > 0;
+            println second >= first;
+            println typeof(first);",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "true\ntrue\nnumber\n");
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let output = SharedOutput::new();
+        vm.set_output(Box::new(output.clone()));
+        let mut now = Duration::from_millis(1000);
+        vm.set_clock(Box::new(move || {
+            now += Duration::from_millis(500);
+            now
+        }));
+
+        vm.interpret(String::from("var start = now(); println now() - start;"));
+        assert_eq!(output.contents(), "0.5\n");
+    }
+
+    #[test]
+    fn define_and_set_large_global() {
+        let large_string = "abc".repeat(1_000);
+        let last_value = get_second_to_last_value_on_value_stack(
+            format!(
+                "var big = \"{}\";
+                big = big + \"!\";
+                big;",
+                large_string
+            ),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::String(s)) => {
+                if *s != format!("{}!", large_string) {
+                    panic!("Expected the large string, got {} bytes", s.len());
+                }
+            }
+            _ => panic!("Expected a string, got {:?}", last_value),
+        }
+    }
+
+    #[test]
+    fn return_value_from_function() {
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from(
+                "fun add(a, b) { return a + b; }
+                add(2, 3);",
+            ),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Int(n)) => {
+                if n != 5 {
+                    panic!("Expected 5, got {}", n);
+                }
+            }
+            _ => panic!("Expected 5, got {:?}", last_value),
+        }
+
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from(
+                "fun nothing() { return; }
+                nothing();",
+            ),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Nil) => {}
+            _ => panic!("Expected nil, got {:?}", last_value),
+        }
+    }
+
+    fn expect_int(source: &str, expected: i64) {
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from(source),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Int(n)) => {
+                if n != expected {
+                    panic!("Expected {} for `{}`, got {}", expected, source, n);
+                }
+            }
+            _ => panic!(
+                "Expected {} for `{}`, got {:?}",
+                expected, source, last_value
+            ),
+        }
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        expect_int("6 & 3;", 2);
+        expect_int("6 | 3;", 7);
+        expect_int("6 ^ 3;", 5);
+        expect_int("1 << 4;", 16);
+        expect_int("256 >> 4;", 16);
+        expect_int("-8 >> 1;", -4);
+
+        // Shifts bind looser than arithmetic, and & binds tighter than |
+        expect_int("1 + 1 << 2;", 8);
+        expect_int("1 | 6 & 3;", 3);
+    }
+
+    #[test]
+    fn bitwise_operator_errors() {
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("1.5 & 1;"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("\"a\" | 1;"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("1 << 64;"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+
+        let mut vm = VM::<Vec<Value>>::new();
+        let result = vm.interpret(String::from("1 >> -1;"));
+        assert!(matches!(result, InterpretResult::RuntimeError));
+    }
+
+    // Output sink that tests can read back after handing it to the VM
+    #[derive(Clone)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedOutput {
+        fn new() -> SharedOutput {
+            SharedOutput(Rc::new(RefCell::new(Vec::new())))
+        }
+
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn print_goes_to_output() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+
+        vm.interpret(String::from("println 1 + 2;"));
+
+        assert_eq!(output.contents(), "3\n");
+    }
+
+    #[test]
+    fn trace_execution() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new().with_trace(true);
+        vm.set_output(Box::new(output.clone()));
+
+        // A global keeps the addition from being folded at compile time
+        vm.interpret(String::from("var a = 1; println a + 2;"));
+
+        let trace = output.contents();
+        assert!(trace.contains("0008 OP_ADD\n"));
+        assert!(trace.contains("[ <script> ][ 1 ][ 2 ]\n0008 OP_ADD"));
+        assert!(trace.ends_with("3\n          [ <script> ]\n0010 OP_NIL\n          [ <script> ][ nil ]\n0011 OP_RETURN\n"));
+    }
+
+    #[test]
+    fn trace_is_off_by_default() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+
+        vm.interpret(String::from("1 + 2;"));
+
+        assert_eq!(output.contents(), "");
+    }
+
+    // Counts how many times the VM flushes its output
+    #[derive(Clone)]
+    struct FlushCounter(Rc<RefCell<usize>>);
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            *self.0.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_after_each_print() {
+        let flushes = FlushCounter(Rc::new(RefCell::new(0)));
+        let mut vm = VM::<Vec<Value>>::new().with_flush_on_print(true);
+        vm.set_output(Box::new(flushes.clone()));
+
+        vm.interpret(String::from("println 1; println 2; 3; println 4;"));
+
+        assert_eq!(*flushes.0.borrow(), 3);
+    }
+
+    #[test]
+    fn no_flush_when_buffering() {
+        let flushes = FlushCounter(Rc::new(RefCell::new(0)));
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(flushes.clone()));
+
+        vm.interpret(String::from("println 1; println 2;"));
+
+        assert_eq!(*flushes.0.borrow(), 0);
+    }
+
+    fn expect_boolean(source: &str, expected: bool) {
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from(source),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Boolean(b)) => {
+                if b != expected {
+                    panic!("Expected {} for `{}`, got {}", expected, source, b);
+                }
+            }
+            _ => panic!(
+                "Expected {} for `{}`, got {:?}",
+                expected, source, last_value
+            ),
+        }
+    }
+
+    #[test]
+    fn int_and_float_equality() {
+        expect_boolean("1 == 1.0;", true);
+        expect_boolean("1.0 == 1;", true);
+        expect_boolean("1 == 1;", true);
+        expect_boolean("1 == 1.5;", false);
+        expect_boolean("2 < 2.5;", true);
+        expect_boolean("3 > 2.5;", true);
+        expect_boolean("-1 < 0;", true);
+    }
+
+    #[test]
+    fn integer_division() {
+        expect_int("7 / 2;", 3);
+        expect_int("-7 / 2;", -3);
+        expect_int("2 * 3 - 10;", -4);
+
+        let last_value = get_second_to_last_value_on_value_stack(
+            String::from("7.0 / 2;"),
+            TestValueStack::new(&mut Vec::new()),
+        );
+        match last_value {
+            Some(Value::Number(n)) => {
+                if n != 3.5 {
+                    panic!("Expected 3.5, got {}", n);
+                }
+            }
+            _ => panic!("Expected 3.5, got {:?}", last_value),
+        }
+
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(SharedOutput::new()));
+        assert_eq!(
+            vm.interpret(String::from("1 / 0;")) as u8,
+            InterpretResult::RuntimeError as u8
+        );
+    }
+
+    #[test]
+    fn float_formatting() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+
+        vm.interpret(String::from(
+            "println 1.0; println 2.5; println 1; println 7 / 2; println 1 + 0.5; println \"n: \" + 2.0;",
+        ));
+
+        assert_eq!(output.contents(), "1.0\n2.5\n1\n3\n1.5\nn: 2.0\n");
+    }
+
+    #[test]
+    fn int_literals_too_big_for_an_int() {
+        let (result, output) = run_and_capture(
+            "println 9223372036854775807;
+            println 10000000000000000000;
+            println 10000000000000000000 == 10000000000000000000.0;",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "9223372036854775807\n1e19\ntrue\n");
+    }
+
+    fn run_and_capture(source: &str) -> (InterpretResult, String) {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+
+        let result = vm.interpret(String::from(source));
+        (result, output.contents())
+    }
+
+    #[test]
+    fn methods_and_initializers() {
+        let (result, output) = run_and_capture(
+            "class Counter {
+                init(start) { this.count = start; }
+                increment() { this.count = this.count + 1; return this.count; }
+            }
+            var c = Counter(5);
+            c.increment();
+            println c.increment();
+            var increment = c.increment;
+            println increment();",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "7\n8\n");
+    }
+
+    #[test]
+    fn initializers_return_the_instance() {
+        let (result, output) = run_and_capture(
+            "class Point {
+                init(x, y) {
+                    this.x = x;
+                    if (y == nil) return;
+                    this.y = y;
+                }
+            }
+            var p = Point(1, 2);
+            println p;
+            println Point(3, nil).x;
+            println p.init(5, 6) == p;
+            println p.x;",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "Point instance\n3\ntrue\n5\n");
+    }
+
+    #[test]
+    fn instance_equality_with_equals() {
+        let (_, output) = run_and_capture(
+            "class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                equals(other) { return this.x == other.x and this.y == other.y; }
+            }
+            println Point(1, 2) == Point(1, 2);
+            println Point(1, 2) == Point(2, 1);
+            println Point(1, 2) == 1;",
+        );
+
+        assert_eq!(output, "true\nfalse\nfalse\n");
+    }
+
+    #[test]
+    fn instance_equality_without_equals() {
+        let (_, output) = run_and_capture(
+            "class Thing {}
+            var a = Thing();
+            var b = Thing();
+            println a == a;
+            println a == b;",
+        );
+
+        assert_eq!(output, "true\nfalse\n");
+    }
+
+    #[test]
+    fn recursive_equals_falls_back_to_identity() {
+        let (result, output) = run_and_capture(
+            "class Loop {
+                equals(other) { return this == other; }
+            }
+            var l = Loop();
+            println l == l;
+            println Loop() == Loop();",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "true\nfalse\n");
+    }
+
+    #[test]
+    fn poly_eval() {
+        let (_, output) = run_and_capture(
+            "println poly_eval(\"x^2 + x - 1\", 2); println poly_eval(\"3x^2 - x\", 0.5);",
+        );
+        assert_eq!(output, "5.0\n0.25\n");
+
+        let (result, _) = run_and_capture("poly_eval(\"x^\", 1);");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+    }
+
+    #[test]
+    fn print_uses_to_string() {
+        let (result, output) = run_and_capture(
+            "class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                toString() { return \"(\" + this.x + \", \" + this.y + \")\"; }
+            }
+            class Plain {}
+            println Point(1, 2);
+            println Plain();",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "(1, 2)\nPlain instance\n");
+    }
+
+    #[test]
+    fn to_string_must_return_a_string() {
+        let (result, _) = run_and_capture(
+            "class Broken { toString() { return 1; } }
+            println Broken();",
+        );
+
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+    }
+
+    #[test]
+    fn recursive_to_string_falls_back_to_default() {
+        let (result, output) = run_and_capture(
+            "class Loud { toString() { println this; return \"loud\"; } }
+            println Loud();",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "Loud instance\nloud\n");
+    }
+
+    #[test]
+    fn limit_of_continuous_function() {
+        let (_, output) = run_and_capture(
+            "fun square(x) { return x * x; }
+            println limit(square, 2);
+            println limit(\"x^2 + 1\", 3);",
+        );
+
+        assert_eq!(output, "4.0\n10.0\n");
+    }
+
+    #[test]
+    fn limit_of_step_discontinuity() {
+        let (_, output) = run_and_capture(
+            "fun step(x) { if (x < 0) return -1; return 1; }
+            println limit(step, 0);
+            println limit(step, 5);",
+        );
+
+        assert_eq!(output, "nil\n1.0\n");
+    }
+
+    #[test]
+    fn parse_json() {
+        // Lox strings can't contain quotes, so the JSON is handed over as a global
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.globals.insert(
+            String::from("source"),
+            Value::String(r#"{"a": 1, "b": [2, 3]}"#.into()),
+        );
+
+        let result = vm.interpret(String::from(
+            "var data = json(source);
+            println data.a;
+            println data.b;
+            println data;",
+        ));
+        let output = output.contents();
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "1\n[2, 3]\nObject instance\n");
+    }
+
+    #[test]
+    fn deeply_nested_json_is_a_runtime_error() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.globals.insert(
+            String::from("source"),
+            Value::String("[".repeat(20_000).into()),
+        );
+
+        let result = vm.interpret(String::from("json(source);"));
+
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(vm
+            .last_error()
+            .unwrap()
+            .message
+            .starts_with("<json> Nesting too deep"));
+    }
+
+    #[test]
+    fn json_files() {
+        let path = std::env::temp_dir().join(format!("rlox_json_{}.json", std::process::id()));
+        let path = path.to_str().unwrap().replace('\\', "/");
+
+        let (result, output) = run_and_capture(
+            format!(
+                "var path = \"{}\";
+                to_json_file({{\"name\": \"lox\", \"tags\": [1, 2.5, true, nil]}}, path);
+                var data = from_json_file(path);
+                println data.name;
+                println data.tags;",
+                path
+            )
+            .as_str(),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "lox\n[1, 2.5, true, nil]\n");
+
+        let (result, output) = run_and_capture("from_json_file(\"/no/such/file.json\");");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("<from_json_file> Couldn't read /no/such/file.json"));
+
+        let (result, output) = run_and_capture("to_json_file(clock, \"unused.json\");");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("<to_json_file>"));
+
+        let (result, output) = run_and_capture("from_json_file(1);");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("<from_json_file> expects a string"));
+    }
+
+    #[test]
+    fn parse_invalid_json() {
+        let (result, output) = run_and_capture(r#"json("[1, 2");"#);
+
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("<json> Expected ',' or ']' at position 5"));
+    }
+
+    fn allocations_for(source: String) -> usize {
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+
+        reset_alloc_count();
+        vm.interpret(source);
+        alloc_count()
+    }
+
+    #[test]
+    fn string_concatenation_allocates_a_fixed_amount_per_iteration() {
+        let concat_loop = |n: usize| {
+            format!(
+                "var s = \"\"; for (var i = 0; i < {}; i = i + 1) {{ s = s + \"a\"; }}",
+                n
+            )
+        };
+
+        let baseline = allocations_for(concat_loop(0));
+        let small = allocations_for(concat_loop(100)) - baseline;
+        let large = allocations_for(concat_loop(200)) - baseline;
+
+        // Each `+` makes one new string, so doubling the iterations should
+        // roughly double the number of allocations. This counts allocations,
+        // not bytes: the new string still copies both operands, so the bytes
+        // copied grow with the length of `s`.
+        assert!(small > 0);
+        assert!(large <= small * 2 + small / 10, "{} vs {}", small, large);
+    }
+
+    #[test]
+    fn copying_strings_does_not_allocate() {
+        let copy_loop = |value: &str, n: usize| {
+            format!(
+                "var s = {}; for (var i = 0; i < {}; i = i + 1) {{ var t = s; t = s; }}",
+                value, n
+            )
+        };
+        let per_iteration = |value: &str| {
+            allocations_for(copy_loop(value, 200)) - allocations_for(copy_loop(value, 100))
+        };
+
+        // Strings are reference counted, so moving one around the stack
+        // costs no more than moving a number
+        assert_eq!(
+            per_iteration("\"a long enough string\""),
+            per_iteration("1")
+        );
+    }
+
+    #[test]
+    fn globals_do_not_copy_their_values() {
+        let define_and_set = |value: &str, n: usize| {
+            format!("var s = {};\n{}", value, "var g = s; g = s;\n".repeat(n))
+        };
+        let per_statement = |value: &str| {
+            allocations_for(define_and_set(value, 200))
+                - allocations_for(define_and_set(value, 100))
+        };
+
+        // Strings are reference counted, so defining and assigning a global
+        // never copies one. A big string costs no more than a number.
+        let large_string = format!("\"{}\"", "abc".repeat(1_000));
+        assert_eq!(per_statement(&large_string), per_statement("1"));
+    }
+
+    #[test]
+    fn instances_share_their_class() {
+        let instantiate = |methods: usize, n: usize| {
+            format!(
+                "class A < B {{ {} }}\nfor (var i = 0; i < {}; i = i + 1) A();",
+                (0..methods)
+                    .map(|i| format!("m{}() {{}} ", i))
+                    .collect::<String>(),
+                n
+            )
+        };
+        let per_instance = |methods: usize| {
+            let base = "class B { inherited() {} }\n";
+            allocations_for(format!("{}{}", base, instantiate(methods, 200)))
+                - allocations_for(format!("{}{}", base, instantiate(methods, 100)))
+        };
+
+        // Neither the method table nor the superclass is copied into each
+        // instance, so a big class is as cheap to instantiate as a small one
+        assert_eq!(per_instance(50), per_instance(0));
+    }
+
+    #[test]
+    fn stringify_values() {
+        let (_, output) = run_and_capture("println stringify(42); println stringify(\"hi\");");
+        assert_eq!(output, "42\n\"hi\"\n");
+
+        let (result, _) = run_and_capture("fun f() {} stringify(f);");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+    }
+
+    #[test]
+    fn stringify_round_trip() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.globals.insert(
+            String::from("source"),
+            Value::String(r#"{"a":1,"b":[2,3,{"c":"d"}]}"#.into()),
+        );
+
+        vm.interpret(String::from(
+            "var data = json(source);
+            data.e = true;
+            println stringify(json(stringify(data)));",
+        ));
+
+        assert_eq!(
+            output.contents(),
+            "{\"a\":1,\"b\":[2,3,{\"c\":\"d\"}],\"e\":true}\n"
+        );
+    }
+
+    #[test]
+    fn chained_comparisons() {
+        expect_boolean("var x = 5; 1 < x < 10;", true);
+        expect_boolean("var x = 20; 1 < x < 10;", false);
+        expect_boolean("var x = 0; 1 < x < 10;", false);
+        expect_boolean("1 < 2 <= 2 < 3;", true);
+        expect_boolean("1 < 2 <= 2 < 2;", false);
+        expect_boolean("3 > 2 >= 2 > 1;", true);
+
+        // The middle operand is only evaluated once
+        let (_, output) = run_and_capture(
+            "var calls = 0;
+            fun middle() { calls = calls + 1; return 5; }
+            println 1 < middle() < 10;
+            println calls;",
+        );
+        assert_eq!(output, "true\n1\n");
+    }
+
+    #[test]
+    fn list_literals_and_indexing() {
+        let (result, output) = run_and_capture(
+            "var xs = [1, 2, 3];
+            println xs;
+            println xs[1];
+            xs[0] = 9;
+            println xs;
+            println [];
+            println [[1, 2], [3]][0][1];",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "[1, 2, 3]\n2\n[9, 2, 3]\n[]\n2\n");
+    }
+
+    #[test]
+    fn printing_a_list_that_contains_itself() {
+        let (result, output) = run_and_capture("var l = [1]; l[0] = l; println l;");
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "[[...]]\n");
+    }
+
+    #[test]
+    fn list_index_errors() {
+        let (result, output) = run_and_capture("var xs = [1, 2]; xs[2];");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("List index 2 is out of bounds for a list of length 2"));
+
+        let (result, _) = run_and_capture("var xs = [1, 2]; xs[-1] = 3;");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+
+        let (result, _) = run_and_capture("var xs = [1, 2]; xs[\"a\"];");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+
+        let (result, _) = run_and_capture("var x = 1; x[0];");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+    }
+
+    #[test]
+    fn instruction_count() {
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+        assert_eq!(vm.instruction_count(), 0);
+
+        // CONSTANT, DEFINE_GLOBAL x2, GET_GLOBAL x2, ADD, PRINT, NIL, RETURN
+        vm.interpret(String::from("var a = 1; var b = 2; println a + b;"));
+        assert_eq!(vm.instruction_count(), 10);
+
+        // A loop runs the same instructions every iteration, so the count
+        // is exact rather than just an upper bound
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.interpret(String::from(
+            "var total = 0; for (var i = 0; i < 10; i = i + 1) { total = total + i; }",
+        ));
+        assert_eq!(vm.instruction_count(), 191);
+    }
+
+    #[test]
+    fn map_literals_and_indexing() {
+        let (result, output) = run_and_capture(
+            "var m = {\"a\": 1, \"b\": 2};
+            println m[\"a\"];
+            println m[\"missing\"];
+            m[\"a\"] = 3;
+            m[\"c\"] = [4];
+            println m;
+            println {};",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "1\nnil\n{a: 3, b: 2, c: [4]}\n{}\n");
+    }
+
+    #[test]
+    fn map_key_errors() {
+        let (result, output) = run_and_capture("var m = {1: 2};");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Map keys must be strings"));
+
+        let (result, _) = run_and_capture("var m = {}; m[1];");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+    }
+
+    #[test]
+    fn stringify_and_clone_maps() {
+        let (result, output) = run_and_capture(
+            "var m = {\"b\": [1, 2], \"a\": nil};
+            var copy = clone(m);
+            copy[\"a\"] = true;
+            println stringify(m);
+            println stringify(copy);",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(
+            output,
+            "{\"a\":null,\"b\":[1,2]}\n{\"a\":true,\"b\":[1,2]}\n"
+        );
+    }
+
+    #[test]
+    fn compare_strings() {
+        expect_boolean("\"apple\" < \"banana\";", true);
+        expect_boolean("\"apple\" > \"banana\";", false);
+        expect_boolean("\"b\" > \"abc\";", true);
+        expect_boolean("\"a\" <= \"a\";", true);
+
+        let (result, output) = run_and_capture("println 1 < \"x\";");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Can't compare a number with a string"));
+    }
+
+    #[test]
+    fn dup_pushes_a_copy_of_the_top_value() {
+        let mut function = Function::new();
+        let constant = function.chunk.write_int(7) as u8;
+        function.chunk.write_code(OpCode::Constant as u8, 1);
+        function.chunk.write_code(constant, 1);
+        function.chunk.write_code(OpCode::Dup as u8, 1);
+        function.chunk.write_code(OpCode::Return as u8, 1);
+
+        let mut all_values = Vec::new();
+        let mut vm = VM::new_with_value_stack(TestValueStack::new(&mut all_values));
+        let closure = Closure::new(Rc::new(function));
+        vm.value_stack.push(Value::Closure(closure.clone()));
+        vm.call(closure, 0);
+
+        let result = vm.run(0);
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+
+        // The closure, the constant, then the duplicate of the constant
+        assert_eq!(all_values.len(), 3);
+        match (&all_values[1], &all_values[2]) {
+            (Value::Int(7), Value::Int(7)) => {}
+            values => panic!("Expected two 7s, got {:?}", values),
+        }
+    }
+
+    #[test]
+    fn errors_report_the_line_of_the_failing_instruction() {
+        let (result, output) = run_and_capture(
+            "print
+            undefined_variable
+            ;",
+        );
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Call from main on line 2\n"), "{}", output);
+
+        let (result, output) = run_and_capture(
+            "var a = 1;
+            println a
+              - \"x\"
+              ;",
+        );
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Call from main on line 3\n"), "{}", output);
+        assert!(output.contains("because RHS isn't a number"), "{}", output);
+
+        let (result, output) = run_and_capture(
+            "fun f() {
+              return nil + 1;
+            }
+            f(
+            );",
+        );
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Call from main on line 5\n"), "{}", output);
+    }
+
+    #[test]
+    fn calling_an_unregistered_native() {
+        let mut vm: VM<Vec<Value>> = VM::<Vec<Value>>::new();
+        let output = SharedOutput::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.globals.insert(
+            String::from("missing"),
+            Value::NativeFunction(NativeFunction {
+                name: String::from("missing"),
+                arity: 0,
+            }),
+        );
+
+        let result = vm.interpret(String::from("missing();"));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output
+            .contents()
+            .contains("No native function named 'missing'"));
+    }
+
+    #[test]
+    fn serialized_programs_run_the_same() {
+        let source = "class Point {
+              init(x, y) { this.x = x; this.y = y; }
+              sum() { return this.x + this.y; }
+            }
+            fun make_adder(n) {
+              fun add(m) { return n + m; }
+              return add;
+            }
+            println Point(1, 2.5).sum();
+            println make_adder(10)(5);
+            println \"done\";";
+
+        let (expected_result, expected_output) = run_and_capture(source);
+
+        let scanner = Scanner::new(String::from(source));
+        let mut compiler = Compiler::new(scanner, FunctionType::Script, None);
+        let function = compiler.compile(None).unwrap().to_owned();
+
+        let bytes = crate::bytecode::serialize_program(&function);
+        let function = crate::bytecode::deserialize_program(&bytes).unwrap();
+
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        let result = vm.interpret_function(function);
+
+        assert_eq!(result as u8, expected_result as u8);
+        assert_eq!(output.contents(), expected_output);
+        assert_eq!(output.contents(), "3.5\n15\ndone\n");
+    }
+
+    #[test]
+    fn stack_corruption() {
+        // Slot 5 is way past the script closure, the only thing on the stack
+        let mut function = Function::new();
+        function.chunk.write_code(OpCode::GetLocal as u8, 1);
+        function.chunk.write_code(5, 1);
+        function.chunk.write_code(OpCode::Return as u8, 1);
+
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        let result = vm.interpret_function(function);
+
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output
+            .contents()
+            .contains("VM stack corruption: slot 5 is out of range for a stack of 1."));
+
+        // Popping the script leaves nothing for Dup to copy
+        let mut function = Function::new();
+        function.chunk.write_code(OpCode::Pop as u8, 1);
+        function.chunk.write_code(OpCode::Dup as u8, 1);
+        function.chunk.write_code(OpCode::Return as u8, 1);
+
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+        let result = vm.interpret_function(function);
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert_eq!(
+            vm.last_error().unwrap().message,
+            "VM stack corruption: Dup can't run on a stack of 0, it needs 1."
+        );
+
+        // Every instruction is checked before it runs, not just the ones
+        // that peek, so popping past the bottom is caught as well
+        for code in [
+            vec![OpCode::Pop, OpCode::Pop, OpCode::Return],
+            vec![OpCode::Nil, OpCode::Rotate, OpCode::Return],
+            vec![OpCode::Nil, OpCode::SetIndex, OpCode::Return],
+            vec![OpCode::Pop, OpCode::Println, OpCode::Return],
+            vec![OpCode::Inherit, OpCode::Return],
+        ] {
+            let mut function = Function::new();
+            for op in code {
+                function.chunk.write_code(op as u8, 1);
+            }
+
+            let mut vm = VM::<Vec<Value>>::new();
+            vm.set_output(Box::new(io::sink()));
+            let result = vm.interpret_function(function);
+            assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+            assert!(vm
+                .last_error()
+                .unwrap()
+                .message
+                .starts_with("VM stack corruption"));
+        }
+    }
+
+    #[test]
+    fn instruction_limit_stops_infinite_loops() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_instruction_limit(Some(10_000));
+
+        let result = vm.interpret(String::from("while (true) {}"));
+
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contents().contains("Execution limit exceeded"));
+        assert_eq!(vm.instruction_count(), 10_001);
+    }
+
+    #[test]
+    fn warnings_are_kept_out_of_program_output() {
+        let output = SharedOutput::new();
+        let warnings = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_warning_output(Box::new(warnings.clone()));
+
+        vm.interpret(String::from("{\n  var unused = 1;\n}\nprintln 1;"));
+
+        assert_eq!(output.contents(), "1\n");
+        assert_eq!(
+            warnings.contents(),
+            "[line 2] Warning: unused variable 'unused'\n"
+        );
+    }
+
+    #[test]
+    fn instruction_limit_applies_to_each_run() {
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.set_instruction_limit(Some(100));
+
+        // Each run is well under the limit, but together they'd go over it
+        for _ in 0..5 {
+            let result = vm.interpret(String::from("var i = 0; while (i < 5) { i = i + 1; }"));
+            assert_eq!(result as u8, InterpretResult::Ok as u8);
+            assert!(vm.instruction_count() < 100);
+        }
+
+        let result = vm.interpret(String::from("while (true) {}"));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert_eq!(vm.instruction_count(), 101);
+    }
+
+    #[test]
+    fn instruction_limit_is_off_by_default() {
+        let (result, output) = run_and_capture(
+            "var i = 0;
+            while (i < 10000) { i = i + 1; }
+            println i;",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "10000\n");
+    }
+
+    #[test]
+    fn max_call_depth() {
+        let source = "fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+            println depth(200);";
+
+        let (result, output) = run_and_capture(source);
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Stack overflow."));
+
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new().with_max_frames(256);
+        vm.set_output(Box::new(output.clone()));
+
+        let result = vm.interpret(String::from(source));
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output.contents(), "200\n");
+    }
+
+    #[test]
+    fn division_by_zero() {
+        for source in [
+            "println 10 / 0;",
+            "println 10.5 / 0;",
+            "println 1 / 0.0;",
+            "println 0.0 / -0.0;",
+        ] {
+            let (result, output) = run_and_capture(source);
+            assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+            assert!(output.contains("Division by zero."), "{}", output);
+        }
+
+        let (result, output) = run_and_capture("println 0 / 5; println 1 / 0.5;");
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "0\n2.0\n");
+    }
+
+    #[test]
+    fn print_and_println() {
+        let (_, output) = run_and_capture("print \"a\"; print \"b\";");
+        assert_eq!(output, "ab");
+
+        let (_, output) = run_and_capture("println \"a\"; print 1; println \"\"; println \"b\";");
+        assert_eq!(output, "a\n1\nb\n");
+    }
+
+    #[test]
+    fn string_escapes() {
+        let (result, output) =
+            run_and_capture(r#"print "a\nb\tc \"quoted\" back\\slash \d"; print "\\";"#);
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "a\nb\tc \"quoted\" back\\slash \\d\\");
+    }
+
+    #[test]
+    fn is_checks_the_class_chain() {
+        let (result, output) = run_and_capture(
+            "class Shape { area() { return 0; } }
+            class Point {}
+            class Square < Shape {
+              init(side) { this.side = side; }
+              area() { return this.side * this.side; }
+            }
+            class Box < Square {}
+            var p = Point();
+            var b = Box(3);
+            println p is Point;
+            println b is Shape;
+            println b is Square;
+            println p is Shape;
+            println 1 is Point;
+            println b.area();",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "true\ntrue\ntrue\nfalse\nfalse\n9\n");
+
+        // Shadowing a class with another of the same name makes a new class
+        let (result, output) = run_and_capture(
+            "class A {}
+            var a = A();
+            {
+                class A {}
+                println a is A;
+                println A() is A;
+            }
+            fun make() { class B {} return B; }
+            println make()() is make();",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "false\ntrue\nfalse\n");
+
+        let (result, output) = run_and_capture("var x = 1; println x is 2;");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Right operand of 'is' must be a class"));
+
+        let (result, output) = run_and_capture("var NotAClass = 1; class A < NotAClass {}");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Superclass must be a class"));
+    }
+
+    #[test]
+    fn super_calls() {
+        let (result, output) = run_and_capture(
+            "class A {
+              f() { return 1; }
+              name() { return \"A\"; }
+              greet() { return \"hi from \" + this.name(); }
+            }
+            class B < A {
+              f() { return super.f() + 10; }
+              name() { return \"B\"; }
+              g() {
+                fun later() { return super.f(); }
+                return later;
+              }
+            }
+            class C < B {
+              f() { return super.f() + 100; }
+            }
+            var c = C();
+            println c.f();
+            println B().g()();
+            println c.greet();",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "111\n1\nhi from B\n");
+
+        // A bound super method keeps its receiver, and getters run right away
+        let (result, output) = run_and_capture(
+            "class A {
+              init(n) { this.n = n; }
+              twice() { return this.n * 2; }
+              size { return this.n; }
+            }
+            class B < A {
+              init(n) { super.init(n + 1); }
+              parts() { return [super.twice, super.size]; }
+            }
+            var parts = B(1).parts();
+            println parts[0]();
+            println parts[1];",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "4\n2\n");
+
+        let (result, output) = run_and_capture(
+            "class A {}
+            class B < A { f() { return super.missing(); } }
+            B().f();",
+        );
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Undefined superclass method 'missing'."));
+    }
+
+    #[test]
+    fn len() {
+        expect_int("len(\"hello\");", 5);
+        expect_int("len(\"héllo\");", 5);
+        expect_int("len(\"\");", 0);
+        expect_int("len([1, 2, 3]);", 3);
+        expect_int("len({\"a\": 1});", 1);
+
+        let (result, output) = run_and_capture("len(42);");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("<len> expects a string, list or map"));
+    }
+
+    #[test]
+    fn string_natives() {
+        let (result, output) = run_and_capture(
+            "println substring(\"hello world\", 6, 11);
+            println substring(\"héllo\", 1, 3);
+            println substring(\"abc\", 1, 1);
+            println index_of(\"hello world\", \"world\");
+            println index_of(\"héllo\", \"llo\");
+            println index_of(\"hello\", \"xyz\");
+            println to_upper(\"héllo\");
+            println to_lower(\"HeLLo\");",
+        );
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "world\nél\n\n6\n2\n-1\nHÉLLO\nhello\n");
+    }
+
+    #[test]
+    fn string_native_errors() {
+        for (source, message) in [
+            ("substring(\"abc\", 1, 4);", "out of bounds"),
+            ("substring(\"abc\", 2, 1);", "out of bounds"),
+            ("substring(\"abc\", -1, 1);", "out of bounds"),
+            ("substring(\"abc\", 0.5, 1);", "expects integer bounds"),
+            ("substring(1, 0, 1);", "<substring> expects a string"),
+            ("index_of(\"abc\", 1);", "<index_of> expects a string"),
+            ("to_upper(nil);", "<to_upper> expects a string"),
+        ] {
+            let (result, output) = run_and_capture(source);
+            assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+            assert!(output.contains(message), "{}", output);
+        }
+    }
+
+    #[test]
+    fn read_line_uses_the_input() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_input(Box::new(io::Cursor::new("Ada\r\nLovelace\nlast")));
+
+        let result = vm.interpret(String::from(
+            "print \"name: \";
+            println \"hello \" + read_line();
+            println read_line();
+            println read_line();
+            println read_line();",
+        ));
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output.contents(), "name: hello Ada\nLovelace\nlast\nnil\n");
+    }
+
+    #[test]
+    fn number_native() {
+        let (result, output) = run_and_capture(
+            "println number(\"3.5\");
+            println number(\" 42 \");
+            println number(\"abc\");
+            println number(\"\");",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "3.5\n42.0\nnil\nnil\n");
+
+        let (result, output) = run_and_capture("number(true);");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("<number> expects a string"));
+    }
+
+    #[test]
+    fn dangling_else_binds_to_the_nearest_if() {
+        let program = |a: bool, b: bool| {
+            format!(
+                "var a = {}; var b = {};
+                if (a) if (b) println \"x\"; else println \"y\";
+                println \"end\";",
+                a, b
+            )
+        };
+
+        assert_eq!(run_and_capture(&program(true, true)).1, "x\nend\n");
+        assert_eq!(run_and_capture(&program(true, false)).1, "y\nend\n");
+        assert_eq!(run_and_capture(&program(false, true)).1, "end\n");
+        assert_eq!(run_and_capture(&program(false, false)).1, "end\n");
+    }
+
+    #[test]
+    fn else_if_chains() {
+        let program = |n: i64| {
+            format!(
+                "var n = {};
+                if (n < 0) println \"negative\";
+                else if (n == 0) println \"zero\";
+                else if (n < 10) println \"small\";
+                else println \"large\";",
+                n
+            )
+        };
+
+        assert_eq!(run_and_capture(&program(-5)).1, "negative\n");
+        assert_eq!(run_and_capture(&program(0)).1, "zero\n");
+        assert_eq!(run_and_capture(&program(3)).1, "small\n");
+        assert_eq!(run_and_capture(&program(30)).1, "large\n");
+    }
+
+    #[test]
+    fn do_while_loops() {
+        let (result, output) = run_and_capture("do println \"once\"; while (false);");
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "once\n");
+
+        let (result, output) = run_and_capture(
+            "var i = 0;
+            do {
+              i = i + 1;
+              println i;
+            } while (i < 3);",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let program = |expression: &str| {
+            format!(
+                "var calls = 0;
+                fun sideEffect() {{ calls = calls + 1; return true; }}
+                println {};
+                println calls;",
+                expression
+            )
+        };
+
+        let cases = [
+            ("true or sideEffect()", "true\n0\n"),
+            ("false or sideEffect()", "true\n1\n"),
+            ("false and sideEffect()", "false\n0\n"),
+            ("true and sideEffect()", "true\n1\n"),
+        ];
+
+        for (expression, expected) in cases {
+            let (result, output) = run_and_capture(&program(expression));
+            assert_eq!(result as u8, InterpretResult::Ok as u8, "{}", expression);
+            assert_eq!(output, expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn multiple_variable_declaration() {
+        let (result, output) = run_and_capture(
+            "var a = 1, b, c = a + 2;
+            println a;
+            println b;
+            println c;
+            {
+              var x = \"x\", y = x + \"y\", z = y + \"z\";
+              println x + y + z;
+            }",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "1\nnil\n3\nxxyxyz\n");
+    }
+
+    #[test]
+    fn assert_native() {
+        let (result, output) = run_and_capture("assert(1 == 1, \"math broke\");");
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "");
+
+        let (result, output) = run_and_capture("assert(false, \"boom\");");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Assertion failed: boom"));
+
+        let (result, _) = run_and_capture("assert(nil, \"nil is falsey\");");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+    }
+
+    #[test]
+    fn typeof_native() {
+        let (result, output) = run_and_capture(
+            "class A { m() {} }
+            fun f() {}
+            println typeof(1);
+            println typeof(1.5);
+            println typeof(\"s\");
+            println typeof(true);
+            println typeof(nil);
+            println typeof(f);
+            println typeof(clock);
+            println typeof(A);
+            println typeof(A());
+            println typeof(A().m);
+            println typeof([1]);
+            println typeof({\"a\": 1});",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(
+            output,
+            "number\nnumber\nstring\nboolean\nnil\nfunction\nfunction\nclass\ninstance\nfunction\nlist\nmap\n"
+        );
+    }
+
+    #[test]
+    fn recursive_fib() {
+        // Each call used to copy the whole chunk of `fib`, which made this
+        // crawl. Now calls only bump the function's reference count.
+        let (result, output) = run_and_capture(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            println fib(25);",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "75025\n");
+    }
+
+    #[test]
+    fn getter_methods() {
+        let (result, output) = run_and_capture(
+            "class Circle {
+              init(r) { this.r = r; }
+              area { return 3 * this.r * this.r; }
+              describe() { return \"area \" + this.area; }
+            }
+            var c = Circle(2);
+            println c.area;
+            println c.describe();
+            c.r = 3;
+            println c.area;",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "12\narea 12\n27\n");
+    }
+
+    #[test]
+    fn compound_property_assignment() {
+        let (result, output) = run_and_capture(
+            "class Counter {}
+            var c = Counter();
+            c.n = 1;
+            c.n += 2;
+            println c.n;
+            c.n -= 1;
+            println c.n;
+            c.n *= 5;
+            println c.n;
+            println c.n /= 2;
+            c.name = \"a\";
+            c.name += \"b\";
+            println c.name;",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "3\n2\n10\n5\nab\n");
+    }
+
+    #[test]
+    fn collapsed_comparisons() {
+        let (result, output) = run_and_capture(
+            "println 1 != 2; println 1 != 1; println \"a\" != \"a\";
+            println 2 >= 2; println 1 >= 2; println 2.5 >= 2;
+            println 2 <= 2; println 3 <= 2; println \"a\" <= \"b\";
+            println !(1 == 1);
+            var i = 0;
+            while (i <= 2) i = i + 1;
+            println i;",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(
+            output,
+            "true\nfalse\nfalse\ntrue\nfalse\ntrue\ntrue\nfalse\ntrue\nfalse\n3\n"
+        );
+    }
+
+    #[test]
+    fn read_back_globals() {
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.interpret(String::from("var answer = 42; var name = \"rlox\";"));
+
+        assert!(matches!(vm.get_global("answer"), Some(Value::Int(42))));
+        assert!(vm.get_global("missing").is_none());
+
+        let mut names: Vec<&String> = vm
+            .globals_iter()
+            .filter(|(_, value)| !matches!(value, Value::NativeFunction(_)))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["answer", "name"]);
+    }
+
+    #[test]
+    fn injected_globals() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+
+        vm.set_global("config", Value::Number(2.5));
+        let result = vm.interpret(String::from("println config * 2;"));
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output.contents(), "5.0\n");
+    }
+
+    #[test]
+    fn reusing_a_vm() {
+        let output = SharedOutput::new();
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(output.clone()));
+
+        let result = vm.interpret(String::from(
+            "var kept = \"kept\";
+            fun inner(x) { fun capture() { return x; } return 1 + nil; }
+            fun outer() { var a = 1; return inner(a) + a; }
+            outer();",
+        ));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(vm.frame_count > 0);
+
+        let output = SharedOutput::new();
+        vm.set_output(Box::new(output.clone()));
+        let result = vm.interpret(String::from("println kept; var a = 2; println a;"));
+
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output.contents(), "kept\n2\n");
+        assert_eq!(vm.value_stack.size(), 0);
+        assert_eq!(vm.frame_count, 0);
+        assert!(vm.open_upvalue_head.is_none());
+    }
+
+    #[test]
+    fn last_error() {
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+
+        let result = vm.interpret(String::from(
+            "var a = 1;\nfun f() {\n  return -\"a\";\n}\nf();",
+        ));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+
+        let error = vm.last_error().unwrap();
+        assert_eq!(
+            error.message,
+            "Can't negate non-numeric value: Some(String(\"a\"))"
+        );
+        assert_eq!(error.line, 3);
+        assert_eq!(
+            error.stack_trace,
+            "Frame 0 -- Call from main on line 5\nFrame 1 -- Call from f on line 3\n"
+        );
+
+        vm.interpret(String::from("println 1;"));
+        assert!(vm.last_error().is_none());
+    }
+
+    #[test]
+    fn error_spans() {
+        let source = "var a = 1;\nprintln a + missing;";
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.interpret(String::from(source));
+
+        let (start, length) = vm.last_error().unwrap().span.unwrap();
+        assert_eq!(&source[start..(start + length)], "missing");
+
+        // Bytecode that didn't come from the compiler has nowhere to point
+        let mut function = Function::new();
+        function.chunk.write_code(OpCode::Pop as u8, 1);
+        function.chunk.write_code(OpCode::Dup as u8, 1);
+        function.chunk.write_code(OpCode::Return as u8, 1);
+        vm.interpret_function(function);
+        assert_eq!(vm.last_error().unwrap().span, None);
+    }
+
+    #[test]
+    fn error_at_the_end_of_a_function() {
+        // The failing property access is the last thing `f` runs before
+        // returning, so its ip is right at the end of the chunk
+        let (result, output) =
+            run_and_capture("class A {}\nfun f(a) {\n  return a.missing;\n}\nf(A());");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert_eq!(
+            output,
+            "Frame 0 -- Call from main on line 5\nFrame 1 -- Call from f on line 3\n\nUndefined property 'missing'.\n"
+        );
+    }
+
+    #[test]
+    fn nan_comparisons() {
+        let (result, output) = run_and_capture("println (0 / 0) == (0 / 0);");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Division by zero."));
+
+        let (result, output) = run_and_capture(
+            "var nan = number(\"NaN\");
+            println nan == nan;
+            println nan != nan;
+            println nan == 1;
+            println nan < 1;
+            println nan > 1;
+            println nan <= 1;
+            println nan >= 1;
+            println !(nan < 1);",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(
+            output,
+            "false\ntrue\nfalse\nfalse\nfalse\nfalse\nfalse\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn power_operator() {
+        expect_int("2 ** 10;", 1024);
+        expect_int("2 ** 3 ** 2;", 512);
+        expect_int("2 * 3 ** 2;", 18);
+
+        let (result, output) = run_and_capture("println 2 ** -1; println 4 ** 0.5;");
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "0.5\n2.0\n");
+
+        let (result, output) = run_and_capture("println \"a\" ** 2;");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Operands of ** must be numbers"));
+
+        let (result, output) = run_and_capture("println 2 ** 64;");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Integer overflow in 2 ** 64"));
+    }
+
+    #[test]
+    fn for_in_loops() {
+        let (result, output) = run_and_capture(
+            "var total = 0;
+            for (x in [1, 2, 3]) total = total + x;
+            println total;",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "6\n");
+
+        let (result, output) = run_and_capture(
+            "for (row in [[1, 2], [3]]) { for (x in row) print x; println \"\"; }
+            for (x in []) println x;",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "12\n3\n");
+
+        let (result, output) = run_and_capture("for (x in 1) println x;");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("Can only loop over lists"));
+    }
+
+    #[test]
+    fn addition_errors_report_their_line() {
+        let mut vm = VM::<Vec<Value>>::new();
+        vm.set_output(Box::new(io::sink()));
+
+        let result = vm.interpret(String::from("var a = 1;\nvar b = 2;\n1 + true;"));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        let error = vm.last_error().unwrap();
+        assert_eq!(error.line, 3);
+        assert!(error
+            .message
+            .starts_with("RHS of addition is an invalid addend"));
+        assert!(error.stack_trace.contains("Call from main on line 3"));
+
+        let result = vm.interpret(String::from("\nnil + 1;"));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        let error = vm.last_error().unwrap();
+        assert_eq!(error.line, 2);
+        assert!(error
+            .message
+            .starts_with("LHS of addition can't be added to a number"));
+
+        let result = vm.interpret(String::from("\n\nnil * 2;"));
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        let error = vm.last_error().unwrap();
+        assert_eq!(error.line, 3);
+        assert!(error.message.contains("LHS isn't a number"));
+    }
+
+    #[test]
+    fn chained_properties() {
+        let (result, output) = run_and_capture(
+            "class A {}
+            class B {
+                init() { this.c = 5; }
+                me() { return this; }
+            }
+            var a = A();
+            a.b = B();
+            println a.b.c;
+            a.b.c = 1;
+            println a.b.c;
+            a.b.me().c = 7;
+            println a.b.me().c;",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "5\n1\n7\n");
+    }
+
+    #[test]
+    fn str_native() {
+        let (result, output) = run_and_capture(
+            "class Point {}
+            class Named { toString() { return \"named\"; } }
+            println str(42);
+            println str(1.5);
+            println str(true);
+            println str(nil);
+            println str(\"s\");
+            println str(Point());
+            println str(Named());
+            println \"count: \" + str(false);",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(
+            output,
+            "42\n1.5\ntrue\nnil\ns\nPoint instance\nnamed\ncount: false\n"
+        );
+
+        let (result, output) =
+            run_and_capture("class Broken { toString() { return 1; } } str(Broken());");
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("toString must return a string"));
+    }
+
+    #[test]
+    fn adding_anything_to_a_string() {
+        let (result, output) = run_and_capture(
+            "class A {}
+            println \"v=\" + true;
+            println \"n=\" + nil;
+            println false + \"!\";
+            println nil + \"!\";
+            println \"a: \" + A();
+            println [1, 2] + \"\";
+            println 1 + \"\";",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(
+            output,
+            "v=true\nn=nil\nfalse!\nnil!\na: A instance\n[1, 2]\n1\n"
+        );
+    }
+
+    #[test]
+    fn to_string_is_used_everywhere_a_value_becomes_text() {
+        let (result, output) = run_and_capture(
+            "class P { toString() { return \"p!\"; } }
+            var p = P();
+            println p;
+            println \"x\" + p;
+            println p + \"x\";
+            println [p, [p]];
+            println {\"k\": p};
+            println str([p]);",
+        );
+        assert_eq!(result as u8, InterpretResult::Ok as u8);
+        assert_eq!(output, "p!\nxp!\np!x\n[p!, [p!]]\n{k: p!}\n[p!]\n");
+
+        // A toString that fails inside a list is still an error
+        let (result, output) = run_and_capture(
+            "class Broken { toString() { return 1; } }
+            println \"\" + [Broken()];",
+        );
+        assert_eq!(result as u8, InterpretResult::RuntimeError as u8);
+        assert!(output.contains("toString must return a string"));
+    }
 }
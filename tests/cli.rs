@@ -0,0 +1,232 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn write_script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rlox_{}_{}.rlox", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .expect("Could not run rlox")
+}
+
+#[test]
+fn check_reports_compile_errors() {
+    let path = write_script("check_broken", "var x = 1;\nfun 123() {}\n");
+
+    let output = rlox(&["check", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stdout.contains("[line 2, col 4] Error at 123: Expect function name."));
+    assert!(stdout.contains("    fun 123() {}\n        ^^^"));
+}
+
+#[test]
+fn check_does_not_run_the_program() {
+    let path = write_script("check_valid", "print \"should not be printed\";\n");
+
+    let output = rlox(&["check", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success());
+    assert!(!stdout.contains("should not be printed"));
+}
+
+#[test]
+fn check_reports_warnings_on_stderr() {
+    let path = write_script("check_warning", "{\n  var unused = 1;\n}\n");
+
+    let output = rlox(&["check", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("[line 2] Warning: unused variable 'unused'"));
+}
+
+#[test]
+fn a_lone_path_runs_the_file() {
+    let path = write_script("lone_path", "println 1 + 1;\n");
+
+    let output = rlox(&[path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2\n"));
+}
+
+#[test]
+fn usage_errors() {
+    for args in [
+        &["no_such_mode_or_file"][..],
+        &["compile", "only_input.lox"],
+        &["run"],
+        &["--dump-tokens"],
+    ] {
+        let output = rlox(args);
+
+        assert_eq!(output.status.code(), Some(64), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Usage: rlox"));
+    }
+}
+
+#[test]
+fn compile_then_run() {
+    let path = write_script("compile_source", "var a = 2;\nprintln a * 21;\n");
+    let compiled = std::env::temp_dir().join(format!("rlox_compiled_{}.loxc", std::process::id()));
+
+    let output = rlox(&[
+        "compile",
+        path.to_str().unwrap(),
+        compiled.to_str().unwrap(),
+    ]);
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let output = rlox(&["run", compiled.to_str().unwrap()]);
+    fs::remove_file(&compiled).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+}
+
+#[test]
+fn run_reports_runtime_errors() {
+    let path = write_script("compile_failing", "println 1;\nprintln nil - 1;\n");
+    let compiled =
+        std::env::temp_dir().join(format!("rlox_compiled_failing_{}.loxc", std::process::id()));
+
+    let output = rlox(&[
+        "compile",
+        path.to_str().unwrap(),
+        compiled.to_str().unwrap(),
+    ]);
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let output = rlox(&["run", compiled.to_str().unwrap()]);
+    fs::remove_file(&compiled).unwrap();
+
+    assert_eq!(output.status.code(), Some(70));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("1\n"));
+}
+
+#[test]
+fn compiled_files_that_cannot_be_read_or_written() {
+    let missing = std::env::temp_dir().join(format!("rlox_missing_{}.loxc", std::process::id()));
+
+    let output = rlox(&["run", missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(66));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Couldn't open"));
+
+    let path = write_script("compile_unwritable", "println 1;\n");
+    let unwritable =
+        std::env::temp_dir().join(format!("rlox_no_such_dir_{}/out.loxc", std::process::id()));
+
+    let output = rlox(&[
+        "compile",
+        path.to_str().unwrap(),
+        unwritable.to_str().unwrap(),
+    ]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(74));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Couldn't write"));
+}
+
+#[test]
+fn run_rejects_source_files() {
+    let path = write_script("run_source", "print 1;\n");
+
+    let output = rlox(&["run", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Not a compiled rlox program"));
+}
+
+#[test]
+fn dump_tokens() {
+    let path = write_script("dump_tokens", "var x = 1.5;\nprintln \"hi\" @");
+
+    let output = rlox(&["--dump-tokens", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "1:0 Var \"var\"
+1:4 Identifier \"x\"
+1:6 Equal \"=\"
+1:8 Number \"1.5\"
+1:11 Semicolon \";\"
+2:0 Println \"println\"
+2:8 String \"\\\"hi\\\"\"
+2:13 Error \"@\" Unexpected character '@'.
+2:14 Eof \"\"
+"
+    );
+}
+
+#[test]
+fn dump_bytecode() {
+    let path = write_script(
+        "dump_bytecode",
+        "fun greet(name) { println \"hi \" + name; }\ngreet(\"you\");\n",
+    );
+
+    let output = rlox(&["--dump-bytecode", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success());
+    assert!(stdout.contains("==== <script> ===="));
+    assert!(stdout.contains("==== <fn greet> ===="));
+    assert!(stdout.contains("OP_RETURN"));
+    assert!(stdout.contains("OP_ADD"));
+    assert!(!stdout.contains("hi you"));
+}
+
+fn rlox_with_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Could not run rlox");
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn run_from_stdin() {
+    let output = rlox_with_stdin(&["-"], "var a = 1;\nprintln a + 1;\n");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+
+    let output = rlox_with_stdin(&["-"], "println 1 +;\n");
+    assert_eq!(output.status.code(), Some(65));
+
+    let output = rlox_with_stdin(&["-"], "println nil + 1;\n");
+    assert_eq!(output.status.code(), Some(70));
+}